@@ -1,21 +1,146 @@
 use hex_color::HexColor;
+use serde::{Deserialize, Serialize};
 
-pub struct ColorList<'a> {
-    colors: Vec<ColorEntry<'a>>,
+use crate::color_space::{self, from_hex, to_hex};
+use crate::palette_format::PaletteJson;
+use crate::render_pipeline::WebImage;
+
+/// Entries in a palette taken from an image.
+pub const EXTRACTED_COLORS: usize = 6;
+/// WCAG AA contrast for large text and graphics, the least an accent gets.
+pub const ACCENT_CONTRAST: f64 = 3.0;
+/// WCAG AA contrast for body text, what a fixed foreground is raised to.
+pub const TEXT_CONTRAST: f64 = 4.5;
+// extraction looks at about this many pixels, evenly spread over the image
+const SAMPLE_PIXELS: usize = 16384;
+
+/// Where the palette cycled by the color panel comes from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PaletteSource {
+    #[default]
+    BuiltIn,
+    /// Extracted from the active image when it was loaded.
+    Image,
+    /// An imported palette, by index into the saved ones.
+    Saved(usize),
+}
+
+impl PaletteSource {
+    /// The source after this one, through every saved palette and back.
+    pub fn next(&self, saved: usize) -> PaletteSource {
+        match self {
+            PaletteSource::BuiltIn => PaletteSource::Image,
+            PaletteSource::Image if saved > 0 => PaletteSource::Saved(0),
+            PaletteSource::Saved(i) if i + 1 < saved => PaletteSource::Saved(i + 1),
+            _ => PaletteSource::BuiltIn,
+        }
+    }
+
+    /// The same source once saved palette `index` is deleted.
+    pub fn without_saved(self, index: usize) -> PaletteSource {
+        match self {
+            PaletteSource::Saved(i) if i == index => PaletteSource::BuiltIn,
+            PaletteSource::Saved(i) if i > index => PaletteSource::Saved(i - 1),
+            source => source,
+        }
+    }
+}
+
+/// How a contrast ratio rates against WCAG 2, for normal sized text.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum ContrastLevel {
+    Fail,
+    /// Passes AA only for large text and graphics, 3:1.
+    AaLarge,
+    /// 4.5:1
+    Aa,
+    /// 7:1
+    Aaa,
+}
+
+impl ContrastLevel {
+    pub fn from_ratio(ratio: f64) -> ContrastLevel {
+        match ratio {
+            r if r >= 7.0 => ContrastLevel::Aaa,
+            r if r >= TEXT_CONTRAST => ContrastLevel::Aa,
+            r if r >= ACCENT_CONTRAST => ContrastLevel::AaLarge,
+            _ => ContrastLevel::Fail,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContrastLevel::Fail => "Fail",
+            ContrastLevel::AaLarge => "AA large",
+            ContrastLevel::Aa => "AA",
+            ContrastLevel::Aaa => "AAA",
+        }
+    }
+}
+
+/// A named list of palette entries. Serialises as the JSON palette format,
+/// see `palette_format`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(into = "PaletteJson", try_from = "PaletteJson")]
+pub struct ColorList {
+    name: String,
+    colors: Vec<ColorEntry>,
     index: usize,
 }
 
-impl <'a> ColorList<'a> {
+impl ColorList {
     pub fn new() -> Self {
-        let mut list = ColorList { 
-            colors: Vec::new(), 
-            index: 0, 
+        let mut list = ColorList {
+            name: String::from("Built-in"),
+            colors: Vec::new(),
+            index: 0,
         };
         list.instantiate_colors();
         list
     }
 
-    fn add(&mut self, bg_hex: &'a str, fg_hex: &'a str, accent: &'a str) {
+    /// `None` for an empty list, every palette needs an entry to show.
+    pub fn from_entries(name: String, colors: Vec<ColorEntry>) -> Option<Self> {
+        if colors.is_empty() {
+            return None;
+        }
+        Some(ColorList { name, colors, index: 0 })
+    }
+
+    /// A palette derived from the colors of `img`, see `from_colors`.
+    /// Falls back to the built-in colors for an image with nothing opaque
+    /// to sample.
+    pub fn from_image(img: &WebImage) -> Self {
+        let dominant = extract_colors(img, EXTRACTED_COLORS);
+        ColorList::from_colors(String::from("From image"), &dominant).unwrap_or_else(ColorList::new)
+    }
+
+    /// Each color becomes a background, with a foreground and an accent
+    /// from the other colors picked to stay readable on it.
+    pub fn from_colors(name: String, colors: &[HexColor]) -> Option<Self> {
+        let entries = colors.iter()
+            .map(|&bg| {
+                let fg = readable_foreground(bg);
+                let accent = colors.iter()
+                    .copied()
+                    .filter(|&other| other != bg)
+                    .max_by(|&a, &b| contrast_ratio(a, bg).total_cmp(&contrast_ratio(b, bg)))
+                    .unwrap_or(fg);
+                ColorEntry::from_colors(bg, fg, ensure_contrast(accent, bg, ACCENT_CONTRAST))
+            })
+            .collect();
+        ColorList::from_entries(name, entries)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entries(&self) -> &[ColorEntry] {
+        &self.colors
+    }
+
+    fn add(&mut self, bg_hex: &str, fg_hex: &str, accent: &str) {
         self.colors.push(ColorEntry::new(bg_hex, fg_hex, accent));
     }
 
@@ -27,30 +152,46 @@ impl <'a> ColorList<'a> {
     }
 
     pub fn next_color(&mut self) -> ColorEntry {
-        let color: ColorEntry = self.colors[*&self.index];
+        let color = self.get_color(self.index).clone();
         self.index += 1;
         color
     }
 
-    pub fn get_color(&self, index: usize) -> ColorEntry {
-        let mut i = index;
-        if i >= self.colors.len() {
-            i = self.colors.len() - 1;
-        }
-        if i < 0 {
-            i = 0;
+    /// The entry at `index`, wrapping around so the panel's +/- cycle.
+    pub fn get_color(&self, index: usize) -> &ColorEntry {
+        &self.colors[index % self.colors.len()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// The same palette with every entry made readable, see
+    /// `ColorEntry::readable`.
+    pub fn readable(&self) -> ColorList {
+        ColorList {
+            colors: self.colors.iter().map(ColorEntry::readable).collect(),
+            ..self.clone()
         }
-        self.colors[i]
+    }
+
+    /// Indices of the entries whose text falls short of AA.
+    pub fn failing_entries(&self) -> Vec<usize> {
+        self.colors.iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.passes())
+            .map(|(i, _)| i)
+            .collect()
     }
 }
 
-#[derive(Copy, Clone)]
-pub struct ColorEntry<'a> {
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorEntry {
     pub bg_rgb: HexColor,
     pub fg_rgb: HexColor,
-    pub accent: &'a str,
-    pub bg_hex: &'a str,
-    pub fg_hex: &'a str,
+    pub accent: String,
+    pub bg_hex: String,
+    pub fg_hex: String,
 }
 
 #[derive(Copy, Clone)]
@@ -60,16 +201,69 @@ pub struct ComponentRGB {
     pub b: f64,
 }
 
-impl <'a> ColorEntry<'a> {
-    fn new(bg_hex: &'a str, fg_hex: &'a str, accent: &'a str) -> Self {
+impl ColorEntry {
+    fn new(bg_hex: &str, fg_hex: &str, accent: &str) -> Self {
 
         ColorEntry {
             bg_rgb: HexColor::parse_rgb(bg_hex).expect("couldn't parse hex"),
             fg_rgb: HexColor::parse_rgb(fg_hex).expect("couldn't parse hex"),
-            accent,
-            bg_hex,
-            fg_hex,
+            accent: accent.to_string(),
+            bg_hex: bg_hex.to_string(),
+            fg_hex: fg_hex.to_string(),
+        }
+    }
+
+    pub fn from_colors(bg: HexColor, fg: HexColor, accent: HexColor) -> Self {
+        ColorEntry {
+            bg_rgb: bg,
+            fg_rgb: fg,
+            accent: accent.to_string(),
+            bg_hex: bg.to_string(),
+            fg_hex: fg.to_string(),
+        }
+    }
+
+    /// The accent as a color, the foreground if it isn't a hex triplet.
+    pub fn accent_rgb(&self) -> HexColor {
+        HexColor::parse_rgb(&self.accent).unwrap_or(self.fg_rgb)
+    }
+
+    /// Contrast of the foreground on the background.
+    pub fn text_contrast(&self) -> f64 {
+        contrast_ratio(self.fg_rgb, self.bg_rgb)
+    }
+
+    /// Contrast of the accent on the background.
+    pub fn accent_contrast(&self) -> f64 {
+        contrast_ratio(self.accent_rgb(), self.bg_rgb)
+    }
+
+    /// Whether the foreground reaches AA for body text and the accent AA
+    /// for large text and graphics.
+    pub fn passes(&self) -> bool {
+        self.text_contrast() >= TEXT_CONTRAST && self.accent_contrast() >= ACCENT_CONTRAST
+    }
+
+    /// This entry with the foreground and accent lightened or darkened
+    /// until they pass, see `passes`. Colors that already pass are kept.
+    pub fn readable(&self) -> ColorEntry {
+        let fg = ensure_contrast(self.fg_rgb, self.bg_rgb, TEXT_CONTRAST);
+        let accent = ensure_contrast(self.accent_rgb(), self.bg_rgb, ACCENT_CONTRAST);
+        if fg == self.fg_rgb && accent == self.accent_rgb() {
+            return self.clone();
         }
+        ColorEntry::from_colors(self.bg_rgb, fg, accent)
+    }
+
+    /// Text color for labels drawn on the accent.
+    pub fn accent_ink(&self) -> String {
+        readable_foreground(self.accent_rgb()).to_string()
+    }
+
+    /// Background, foreground and accent as linear RGBA, the way the
+    /// renderer's sRGB targets expect them.
+    pub fn linear_rgba(&self) -> [[f32; 4]; 3] {
+        [self.bg_rgb, self.fg_rgb, self.accent_rgb()].map(|color| color_space::linear_rgba(from_hex(color)))
     }
 
     pub fn bg_rgb(&self) -> ComponentRGB {
@@ -79,5 +273,175 @@ impl <'a> ColorEntry<'a> {
             g: self.bg_rgb.g as f64 / 255.0
         }
     }
-    
-}
\ No newline at end of file
+
+}
+
+/// Up to `count` dominant colors of `img`, most common first. Median cut
+/// in OKLab, so the boxes split along differences people actually see.
+/// Mostly transparent pixels are skipped.
+pub fn extract_colors(img: &WebImage, count: usize) -> Vec<HexColor> {
+    let step = (img.data.len() / 4 / SAMPLE_PIXELS).max(1);
+    let samples: Vec<[f64; 3]> = img.data.chunks_exact(4)
+        .step_by(step)
+        .filter(|px| px[3] >= 128)
+        .map(|px| color_space::srgb_to_oklab(from_hex(HexColor::rgb(px[0], px[1], px[2]))))
+        .collect();
+    if samples.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![samples];
+    while boxes.len() < count {
+        // split the box that spans the most, weighted by how much it holds
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (axis, range) = widest_axis(b);
+                (i, axis, range * (b.len() as f64).sqrt())
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+        let (index, axis) = match widest {
+            Some((index, axis, spread)) if spread > 0.0 => (index, axis),
+            _ => break,
+        };
+
+        let mut split = boxes.swap_remove(index);
+        split.sort_by(|a, b| a[axis].total_cmp(&b[axis]));
+        // cut at the median, moved to where the value changes so a flat
+        // patch of color isn't averaged with its neighbour
+        let median = split[split.len() / 2][axis];
+        let before = split.partition_point(|lab| lab[axis] < median);
+        let after = split.partition_point(|lab| lab[axis] <= median);
+        let at = if before == 0 || (after < split.len() && after - split.len() / 2 < split.len() / 2 - before) {
+            after
+        } else {
+            before
+        };
+        let upper = split.split_off(at);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    boxes.iter()
+        .map(|b| {
+            let mut sum = [0.0; 3];
+            for lab in b {
+                for axis in 0..3 {
+                    sum[axis] += lab[axis];
+                }
+            }
+            let n = b.len() as f64;
+            to_hex(color_space::oklab_to_srgb([sum[0] / n, sum[1] / n, sum[2] / n]))
+        })
+        .collect()
+}
+
+fn widest_axis(samples: &[[f64; 3]]) -> (usize, f64) {
+    (0..3)
+        .map(|axis| {
+            let (min, max) = samples.iter().fold((f64::MAX, f64::MIN), |(min, max), lab| {
+                (min.min(lab[axis]), max.max(lab[axis]))
+            });
+            (axis, max - min)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+/// White or black, whichever reads better on `bg`. One of them always
+/// clears the 4.5:1 WCAG AA asks of body text.
+pub fn readable_foreground(bg: HexColor) -> HexColor {
+    let white = HexColor::rgb(255, 255, 255);
+    let black = HexColor::rgb(0, 0, 0);
+    if contrast_ratio(white, bg) >= contrast_ratio(black, bg) {
+        white
+    } else {
+        black
+    }
+}
+
+// lightens or darkens `color` in OKLab, keeping its hue, until it has `min` contrast on `bg`
+fn ensure_contrast(color: HexColor, bg: HexColor, min: f64) -> HexColor {
+    if contrast_ratio(color, bg) >= min {
+        return color;
+    }
+    let [l, a, b] = color_space::srgb_to_oklab(from_hex(color));
+    let toward_white = readable_foreground(bg) == HexColor::rgb(255, 255, 255);
+    (1..=20)
+        .map(|step| {
+            let shift = step as f64 * 0.05;
+            let l = if toward_white { (l + shift).min(1.0) } else { (l - shift).max(0.0) };
+            to_hex(color_space::oklab_to_srgb([l, a, b]))
+        })
+        .find(|&candidate| contrast_ratio(candidate, bg) >= min)
+        .unwrap_or_else(|| readable_foreground(bg))
+}
+
+/// WCAG relative luminance, 0 for black to 1 for white.
+pub fn relative_luminance(color: HexColor) -> f64 {
+    color_space::luminance(color_space::srgb_to_linear_rgb(from_hex(color)))
+}
+
+/// WCAG contrast ratio between two colors, from 1 to 21.
+pub fn contrast_ratio(a: HexColor, b: HexColor) -> f64 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ImageMetadata;
+
+    const WHITE: HexColor = HexColor::rgb(255, 255, 255);
+    const BLACK: HexColor = HexColor::rgb(0, 0, 0);
+
+    fn image(pixels: &[[u8; 4]]) -> WebImage {
+        WebImage {
+            width: pixels.len() as u32,
+            height: 1,
+            data: pixels.concat(),
+            metadata: ImageMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn rates_contrast() {
+        assert!((contrast_ratio(BLACK, WHITE) - 21.0).abs() < 1e-9);
+        assert_eq!(contrast_ratio(WHITE, BLACK), contrast_ratio(BLACK, WHITE));
+        assert_eq!(contrast_ratio(WHITE, WHITE), 1.0);
+
+        assert_eq!(ContrastLevel::from_ratio(21.0), ContrastLevel::Aaa);
+        assert_eq!(ContrastLevel::from_ratio(7.0), ContrastLevel::Aaa);
+        assert_eq!(ContrastLevel::from_ratio(4.5), ContrastLevel::Aa);
+        assert_eq!(ContrastLevel::from_ratio(3.0), ContrastLevel::AaLarge);
+        assert_eq!(ContrastLevel::from_ratio(2.9), ContrastLevel::Fail);
+    }
+
+    #[test]
+    fn raises_contrast_to_the_target() {
+        let grey = HexColor::rgb(128, 128, 128);
+        for (color, bg) in [(HexColor::rgb(150, 140, 120), grey), (HexColor::rgb(30, 60, 200), HexColor::rgb(20, 20, 60))] {
+            for min in [ACCENT_CONTRAST, TEXT_CONTRAST] {
+                let raised = ensure_contrast(color, bg, min);
+                assert!(ContrastLevel::from_ratio(contrast_ratio(raised, bg)) >= ContrastLevel::from_ratio(min));
+            }
+        }
+        // already readable, left alone
+        assert_eq!(ensure_contrast(WHITE, BLACK, TEXT_CONTRAST), WHITE);
+    }
+
+    #[test]
+    fn extracts_the_most_common_colors_first() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let hidden = [0, 255, 0, 0];
+        let colors = extract_colors(&image(&[red, red, red, blue, hidden, hidden, hidden, hidden]), 3);
+        assert_eq!(colors, vec![HexColor::rgb(255, 0, 0), HexColor::rgb(0, 0, 255)]);
+
+        assert_eq!(extract_colors(&image(&[hidden]), 3), Vec::new());
+        assert_eq!(extract_colors(&image(&[red]), 0), Vec::new());
+    }
+}
@@ -0,0 +1,344 @@
+use hex_color::HexColor;
+
+// Colors are `[f64; 3]` throughout. sRGB and Display P3 are gamma encoded,
+// 0-1. Linear RGB uses the sRGB primaries. XYZ and CIELAB are relative to
+// D65 with Y = 1 for white. Hues are in degrees.
+//
+// Conversions the app doesn't call yet are only built for the tests, which
+// keep them checked against reference values until something needs them.
+
+/// D65 white in XYZ, the reference white for CIELAB. Taken from the sRGB
+/// matrix so white comes out neutral.
+#[cfg(test)]
+pub const D65: [f64; 3] = [0.9504559271, 1.0, 1.0890577508];
+
+// CIE constants for the linear part of the L* curve
+#[cfg(test)]
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+#[cfg(test)]
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
+
+#[cfg(test)]
+const LINEAR_RGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4123907993, 0.3575843394, 0.1804807884],
+    [0.2126390059, 0.7151686788, 0.0721923154],
+    [0.0193308187, 0.1191947798, 0.9505321522],
+];
+
+#[cfg(test)]
+const XYZ_TO_LINEAR_RGB: [[f64; 3]; 3] = [
+    [3.2409699419, -1.5373831776, -0.4986107603],
+    [-0.9692436363, 1.8759675015, 0.0415550574],
+    [0.0556300797, -0.2039769589, 1.0569715142],
+];
+
+#[cfg(test)]
+const LINEAR_P3_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4865709486, 0.2656676932, 0.1982172852],
+    [0.2289745641, 0.6917385218, 0.0792869141],
+    [0.0000000000, 0.0451133819, 1.0439443689],
+];
+
+#[cfg(test)]
+const XYZ_TO_LINEAR_P3: [[f64; 3]; 3] = [
+    [2.4934969119, -0.9313836179, -0.4027107845],
+    [-0.8294889696, 1.7626640603, 0.0236246858],
+    [0.0358458302, -0.0761723893, 0.9568845240],
+];
+
+#[cfg(test)]
+fn multiply(matrix: &[[f64; 3]; 3], [x, y, z]: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * x + row[1] * y + row[2] * z)
+}
+
+/// Decodes an sRGB channel, 0-1, to linear light.
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear channel with the sRGB curve.
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear_rgb(rgb: [f64; 3]) -> [f64; 3] {
+    rgb.map(srgb_to_linear)
+}
+
+#[cfg(test)]
+pub fn linear_rgb_to_srgb(rgb: [f64; 3]) -> [f64; 3] {
+    rgb.map(linear_to_srgb)
+}
+
+pub fn from_hex(color: HexColor) -> [f64; 3] {
+    [color.r, color.g, color.b].map(|c| c as f64 / 255.0)
+}
+
+/// Rounds to 8 bits, out of gamut channels are clipped.
+pub fn to_hex(rgb: [f64; 3]) -> HexColor {
+    let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    HexColor::rgb(r, g, b)
+}
+
+/// An sRGB color the way the renderer's shaders expect it: linear, opaque
+/// and in `f32`.
+pub fn linear_rgba(rgb: [f64; 3]) -> [f32; 4] {
+    let [r, g, b] = srgb_to_linear_rgb(rgb).map(|c| c as f32);
+    [r, g, b, 1.0]
+}
+
+/// Rec. 709 luminance of a linear RGB color, the Y of XYZ.
+pub fn luminance([r, g, b]: [f64; 3]) -> f64 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Hue, saturation and lightness of an sRGB color.
+#[cfg(test)]
+pub fn srgb_to_hsl(rgb: [f64; 3]) -> [f64; 3] {
+    let (max, min, hue) = hue(rgb);
+    let l = (max + min) / 2.0;
+    let s = if max == min { 0.0 } else { (max - min) / (1.0 - (2.0 * l - 1.0).abs()) };
+    [hue, s, l]
+}
+
+#[cfg(test)]
+pub fn hsl_to_srgb([h, s, l]: [f64; 3]) -> [f64; 3] {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    from_hue(h, chroma, l - chroma / 2.0)
+}
+
+/// Hue, saturation and value of an sRGB color.
+#[cfg(test)]
+pub fn srgb_to_hsv(rgb: [f64; 3]) -> [f64; 3] {
+    let (max, min, hue) = hue(rgb);
+    let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+    [hue, s, max]
+}
+
+#[cfg(test)]
+pub fn hsv_to_srgb([h, s, v]: [f64; 3]) -> [f64; 3] {
+    let chroma = v * s;
+    from_hue(h, chroma, v - chroma)
+}
+
+// largest and smallest channel, and the hexagonal hue, 0 for greys
+#[cfg(test)]
+fn hue([r, g, b]: [f64; 3]) -> (f64, f64, f64) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (max, min, hue)
+}
+
+#[cfg(test)]
+fn from_hue(h: f64, chroma: f64, min: f64) -> [f64; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let [r, g, b] = match h as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    [r + min, g + min, b + min]
+}
+
+#[cfg(test)]
+pub fn linear_rgb_to_xyz(rgb: [f64; 3]) -> [f64; 3] {
+    multiply(&LINEAR_RGB_TO_XYZ, rgb)
+}
+
+#[cfg(test)]
+pub fn xyz_to_linear_rgb(xyz: [f64; 3]) -> [f64; 3] {
+    multiply(&XYZ_TO_LINEAR_RGB, xyz)
+}
+
+/// CIE 1976 L*a*b*, L from 0 to 100.
+#[cfg(test)]
+pub fn xyz_to_lab(xyz: [f64; 3]) -> [f64; 3] {
+    let f = |t: f64| if t > LAB_EPSILON { t.cbrt() } else { (LAB_KAPPA * t + 16.0) / 116.0 };
+    let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / D65[i]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+pub fn lab_to_xyz([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let inverse = |f: f64| if f.powi(3) > LAB_EPSILON { f.powi(3) } else { (116.0 * f - 16.0) / LAB_KAPPA };
+    let y = if l > LAB_KAPPA * LAB_EPSILON { fy.powi(3) } else { l / LAB_KAPPA };
+    [inverse(fx) * D65[0], y * D65[1], inverse(fz) * D65[2]]
+}
+
+#[cfg(test)]
+pub fn srgb_to_lab(rgb: [f64; 3]) -> [f64; 3] {
+    xyz_to_lab(linear_rgb_to_xyz(srgb_to_linear_rgb(rgb)))
+}
+
+#[cfg(test)]
+pub fn lab_to_srgb(lab: [f64; 3]) -> [f64; 3] {
+    linear_rgb_to_srgb(xyz_to_linear_rgb(lab_to_xyz(lab)))
+}
+
+/// Björn Ottosson's OKLab, L from 0 to 1.
+pub fn linear_rgb_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+pub fn oklab_to_linear_rgb([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+}
+
+pub fn srgb_to_oklab(rgb: [f64; 3]) -> [f64; 3] {
+    linear_rgb_to_oklab(srgb_to_linear_rgb(rgb))
+}
+
+/// Out of gamut results are clipped per channel.
+pub fn oklab_to_srgb(lab: [f64; 3]) -> [f64; 3] {
+    oklab_to_linear_rgb(lab).map(|c| linear_to_srgb(c.clamp(0.0, 1.0)))
+}
+
+/// Lightness, chroma and hue of an OKLab color.
+#[cfg(test)]
+pub fn oklab_to_oklch([l, a, b]: [f64; 3]) -> [f64; 3] {
+    [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.0)]
+}
+
+#[cfg(test)]
+pub fn oklch_to_oklab([l, c, h]: [f64; 3]) -> [f64; 3] {
+    let h = h.to_radians();
+    [l, c * h.cos(), c * h.sin()]
+}
+
+/// Linear sRGB to linear Display P3. Both share D65 white.
+#[cfg(test)]
+pub fn linear_rgb_to_linear_p3(rgb: [f64; 3]) -> [f64; 3] {
+    multiply(&XYZ_TO_LINEAR_P3, linear_rgb_to_xyz(rgb))
+}
+
+/// Can leave 0-1 for P3 colors outside the sRGB gamut.
+#[cfg(test)]
+pub fn linear_p3_to_linear_rgb(p3: [f64; 3]) -> [f64; 3] {
+    xyz_to_linear_rgb(multiply(&LINEAR_P3_TO_XYZ, p3))
+}
+
+/// Display P3 uses the sRGB transfer curve with wider primaries.
+#[cfg(test)]
+pub fn srgb_to_display_p3(rgb: [f64; 3]) -> [f64; 3] {
+    linear_rgb_to_srgb(linear_rgb_to_linear_p3(srgb_to_linear_rgb(rgb)))
+}
+
+#[cfg(test)]
+pub fn display_p3_to_srgb(p3: [f64; 3]) -> [f64; 3] {
+    linear_rgb_to_srgb(linear_p3_to_linear_rgb(srgb_to_linear_rgb(p3)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f64; 3] = [1.0, 0.0, 0.0];
+    const WHITE: [f64; 3] = [1.0, 1.0, 1.0];
+    const ORANGE: [f64; 3] = [1.0, 128.0 / 255.0, 0.0];
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3], tolerance: f64) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() <= tolerance, "{:?} is not within {} of {:?}", actual, tolerance, expected);
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_matches_reference() {
+        assert!((srgb_to_linear(0.5) - 0.214041).abs() < 1e-6);
+        assert!((srgb_to_linear(0.04) - 0.04 / 12.92).abs() < 1e-9);
+        assert!((linear_to_srgb(0.18) - 0.461356).abs() < 1e-6);
+        for i in 0..=255 {
+            let c = i as f64 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn hsl_and_hsv_match_reference() {
+        assert_close(srgb_to_hsl(ORANGE), [30.117647, 1.0, 0.5], 1e-5);
+        assert_close(srgb_to_hsv(ORANGE), [30.117647, 1.0, 1.0], 1e-5);
+        assert_close(srgb_to_hsl([0.25, 0.5, 0.75]), [210.0, 0.5, 0.5], 1e-9);
+        assert_close(srgb_to_hsv([0.25, 0.5, 0.75]), [210.0, 2.0 / 3.0, 0.75], 1e-9);
+        assert_close(srgb_to_hsl([0.5; 3]), [0.0, 0.0, 0.5], 1e-9);
+
+        for rgb in [RED, ORANGE, [0.2, 0.7, 0.4], [0.9, 0.1, 0.6], [0.3; 3]] {
+            assert_close(hsl_to_srgb(srgb_to_hsl(rgb)), rgb, 1e-9);
+            assert_close(hsv_to_srgb(srgb_to_hsv(rgb)), rgb, 1e-9);
+        }
+    }
+
+    #[test]
+    fn cielab_matches_reference() {
+        assert_close(srgb_to_lab(WHITE), [100.0, 0.0, 0.0], 1e-2);
+        assert_close(srgb_to_lab(RED), [53.2408, 80.0925, 67.2032], 1e-2);
+        assert_close(srgb_to_lab([0.0, 0.0, 1.0]), [32.2970, 79.1875, -107.8602], 1e-2);
+        assert_close(lab_to_srgb(srgb_to_lab(ORANGE)), ORANGE, 1e-9);
+        // dark enough for the linear part of the curve
+        assert!((lab_to_xyz([5.0, 0.0, 0.0])[1] - 5.0 / LAB_KAPPA).abs() < 1e-12);
+        assert_close(xyz_to_lab(lab_to_xyz([5.0, 3.0, -2.0])), [5.0, 3.0, -2.0], 1e-9);
+    }
+
+    #[test]
+    fn oklab_and_oklch_match_reference() {
+        assert_close(srgb_to_oklab(WHITE), [1.0, 0.0, 0.0], 1e-4);
+        assert_close(srgb_to_oklab(RED), [0.627955, 0.224863, 0.125846], 1e-4);
+        assert_close(oklab_to_oklch(srgb_to_oklab(RED)), [0.627955, 0.257683, 29.2339], 1e-3);
+        assert_close(oklab_to_srgb(oklch_to_oklab(oklab_to_oklch(srgb_to_oklab(ORANGE)))), ORANGE, 1e-6);
+    }
+
+    #[test]
+    fn display_p3_matches_reference() {
+        assert_close(srgb_to_display_p3(WHITE), WHITE, 1e-6);
+        assert_close(srgb_to_display_p3(RED), [0.917488, 0.200287, 0.138561], 1e-4);
+        assert_close(display_p3_to_srgb(srgb_to_display_p3(ORANGE)), ORANGE, 1e-6);
+        // P3 red is outside sRGB
+        assert!(display_p3_to_srgb(RED)[0] > 1.0);
+    }
+
+    #[test]
+    fn uniforms_are_linear() {
+        assert_eq!(linear_rgba(WHITE), [1.0; 4]);
+        let [r, g, b, a] = linear_rgba([0.5; 3]);
+        assert!((r - 0.214041).abs() < 1e-6 && r == g && g == b && a == 1.0);
+        assert!((luminance(srgb_to_linear_rgb(WHITE)) - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+
+use crate::histogram::Channel;
+use crate::point_list::{Point, PointList};
+
+/// Most points a curve holds.
+pub const MAX_POINTS: usize = 8;
+/// Texels in the baked curves, the width of their lookup texture.
+pub const CURVE_WIDTH: u32 = 256;
+// closest the black and white points get, so levels never divide by zero
+const MIN_RANGE: f32 = 0.01;
+// space around the curve in its SVG, out of 100, so points at the ends aren't cut off
+const SVG_INSET: f64 = 3.0;
+
+/// Input levels of a channel. Values below `black` are crushed, above
+/// `white` are blown, and `gamma` over 1 lifts what's in between.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedLevels")]
+pub struct Levels {
+    pub black: f32,
+    pub white: f32,
+    pub gamma: f32,
+}
+
+impl Levels {
+    pub fn apply(&self, value: f32) -> f32 {
+        ((value - self.black) / (self.white - self.black)).clamp(0.0, 1.0).powf(1.0 / self.gamma)
+    }
+
+    // the white point gives way to the black point being dragged past it
+    fn clamped(self) -> Self {
+        let black = self.black.clamp(0.0, 1.0 - MIN_RANGE);
+        Levels {
+            black,
+            white: self.white.clamp(black + MIN_RANGE, 1.0),
+            gamma: self.gamma.clamp(0.1, 10.0),
+        }
+    }
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels { black: 0.0, white: 1.0, gamma: 1.0 }
+    }
+}
+
+// what `Levels` are read as, `From` clamps them
+#[derive(Deserialize)]
+#[serde(default)]
+struct SavedLevels {
+    black: f32,
+    white: f32,
+    gamma: f32,
+}
+
+impl Default for SavedLevels {
+    fn default() -> Self {
+        let Levels { black, white, gamma } = Levels::default();
+        SavedLevels { black, white, gamma }
+    }
+}
+
+impl From<SavedLevels> for Levels {
+    fn from(saved: SavedLevels) -> Self {
+        Levels { black: saved.black, white: saved.white, gamma: saved.gamma }.clamped()
+    }
+}
+
+/// A point a curve passes through, both 0-1.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    /// Input level.
+    pub x: f32,
+    /// Output level.
+    pub y: f32,
+}
+
+impl CurvePoint {
+    pub fn new(x: f32, y: f32) -> Self {
+        CurvePoint { x: x.clamp(0.0, 1.0), y: y.clamp(0.0, 1.0) }
+    }
+}
+
+impl Point for CurvePoint {
+    fn position(&self) -> f32 {
+        self.x
+    }
+
+    fn with_position(self, x: f32) -> Self {
+        CurvePoint::new(x, self.y)
+    }
+}
+
+/// A tone curve through 2 to `MAX_POINTS` points kept in input order.
+/// Interpolated with a monotone cubic, so it never overshoots between
+/// points the way a plain spline would.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<CurvePoint>", into = "Vec<CurvePoint>")]
+pub struct Curve {
+    points: PointList<CurvePoint, MAX_POINTS>,
+}
+
+impl Curve {
+    pub fn points(&self) -> &[CurvePoint] {
+        self.points.points()
+    }
+
+    /// Replaces the point at `index`, kept between its neighbours.
+    pub fn set(&mut self, index: usize, point: CurvePoint) {
+        self.points.set(index, point);
+    }
+
+    /// Splits the widest gap between points, on the curve so nothing changes
+    /// until the new point is moved.
+    pub fn add_point(&mut self) {
+        if let Some(x) = self.points.widest_gap() {
+            self.points.insert(CurvePoint::new(x, self.sample(x)));
+        }
+    }
+
+    /// Adds `point` where it is, off the curve if need be. Returns its
+    /// index, `None` when the curve is full.
+    pub fn insert_point(&mut self, point: CurvePoint) -> Option<usize> {
+        self.points.insert(point)
+    }
+
+    /// The point within `radius` of `near`, the closest if there are several.
+    pub fn point_near(&self, near: CurvePoint, radius: f32) -> Option<usize> {
+        self.points().iter()
+            .enumerate()
+            .map(|(i, point)| (i, (point.x - near.x).hypot(point.y - near.y)))
+            .filter(|&(_, distance)| distance <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    pub fn remove_point(&mut self, index: usize) {
+        self.points.remove(index);
+    }
+
+    /// Output level for input `x`, held flat past the first and last points.
+    pub fn sample(&self, x: f32) -> f32 {
+        let points = self.points();
+        let i = match self.points.span(x) {
+            Ok(i) => i,
+            Err(end) => return end.y,
+        };
+        let (from, to) = (points[i], points[i + 1]);
+        let width = to.x - from.x;
+        if width <= 0.0 {
+            return to.y;
+        }
+
+        let slopes = self.slopes();
+        let t = (x - from.x) / width;
+        let (t2, t3) = (t * t, t * t * t);
+        let y = (2.0 * t3 - 3.0 * t2 + 1.0) * from.y
+            + (t3 - 2.0 * t2 + t) * width * slopes[i]
+            + (-2.0 * t3 + 3.0 * t2) * to.y
+            + (t3 - t2) * width * slopes[i + 1];
+        y.clamp(0.0, 1.0)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.points().iter().all(|point| (point.x - point.y).abs() < 1e-4)
+    }
+
+    /// The curve drawn in `color` over the unchanged diagonal, with its points.
+    pub fn svg(&self, color: &str) -> String {
+        let line: Vec<String> = (0..=100)
+            .map(|x| format!("{},{:.1}", x, 100.0 - self.sample(x as f32 / 100.0) * 100.0))
+            .collect();
+        let mut svg = format!("<svg xmlns='http://www.w3.org/2000/svg' viewBox='-{inset} -{inset} {size} {size}'>", inset = SVG_INSET, size = 100.0 + 2.0 * SVG_INSET);
+        svg.push_str("<path d='M0 100 L100 0' stroke='rgba(255, 255, 255, 0.3)' stroke-width='0.5'/>");
+        svg.push_str(&format!("<polyline points='{}' fill='none' stroke='{}' stroke-width='1.5'/>", line.join(" "), color));
+        for point in self.points() {
+            svg.push_str(&format!("<circle cx='{:.1}' cy='{:.1}' r='2' fill='{}'/>", point.x * 100.0, 100.0 - point.y * 100.0, color));
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    /// The levels under a spot on the `svg`, given as fractions of its
+    /// width and height from the top left.
+    pub fn svg_point(across: f64, down: f64) -> CurvePoint {
+        let size = 100.0 + 2.0 * SVG_INSET;
+        let level = |fraction: f64| ((fraction * size - SVG_INSET) / 100.0) as f32;
+        CurvePoint::new(level(across), 1.0 - level(down))
+    }
+
+    // tangents at each point, Fritsch-Carlson limited so every span is monotone
+    fn slopes(&self) -> [f32; MAX_POINTS] {
+        let points = self.points();
+        let mut secants = [0.0; MAX_POINTS];
+        for (i, pair) in points.windows(2).enumerate() {
+            let width = pair[1].x - pair[0].x;
+            secants[i] = if width > 0.0 { (pair[1].y - pair[0].y) / width } else { 0.0 };
+        }
+
+        let last = points.len() - 1;
+        let mut slopes = [0.0; MAX_POINTS];
+        slopes[0] = secants[0];
+        slopes[last] = secants[last - 1];
+        for i in 1..last {
+            let (before, after) = (secants[i - 1], secants[i]);
+            slopes[i] = if before * after <= 0.0 { 0.0 } else { (before + after) / 2.0 };
+        }
+        for i in 0..last {
+            if secants[i] == 0.0 {
+                slopes[i] = 0.0;
+                slopes[i + 1] = 0.0;
+                continue;
+            }
+            let (a, b) = (slopes[i] / secants[i], slopes[i + 1] / secants[i]);
+            let length = (a * a + b * b).sqrt();
+            if length > 3.0 {
+                slopes[i] = 3.0 * a / length * secants[i];
+                slopes[i + 1] = 3.0 * b / length * secants[i];
+            }
+        }
+        slopes
+    }
+}
+
+/// The straight line, every level left as it is.
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::from(vec![CurvePoint::new(0.0, 0.0), CurvePoint::new(1.0, 1.0)])
+    }
+}
+
+impl From<Vec<CurvePoint>> for Curve {
+    fn from(points: Vec<CurvePoint>) -> Self {
+        let points: Vec<CurvePoint> = points.iter().map(|point| CurvePoint::new(point.x, point.y)).collect();
+        PointList::new(&points).map(|points| Curve { points }).unwrap_or_default()
+    }
+}
+
+impl From<Curve> for Vec<CurvePoint> {
+    fn from(curve: Curve) -> Self {
+        curve.points().to_vec()
+    }
+}
+
+/// Levels then a curve for each channel, indexed by `Channel`. Works
+/// on display encoded values, the way levels and curves usually do.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Curves {
+    levels: [Levels; 4],
+    curves: [Curve; 4],
+}
+
+impl Curves {
+    pub fn levels(&self, channel: Channel) -> Levels {
+        self.levels[channel.index()]
+    }
+
+    pub fn curve(&self, channel: Channel) -> &Curve {
+        &self.curves[channel.index()]
+    }
+
+    pub fn set_levels(&mut self, channel: Channel, levels: Levels) {
+        self.levels[channel.index()] = levels.clamped();
+    }
+
+    pub fn set_curve(&mut self, channel: Channel, curve: Curve) {
+        self.curves[channel.index()] = curve;
+    }
+
+    /// Every channel of an sRGB color, RGB levels and curve first.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let master = |value: f32| self.curves[0].sample(self.levels[0].apply(value));
+        [0, 1, 2].map(|c| self.curves[c + 1].sample(self.levels[c + 1].apply(master(rgb[c]))))
+    }
+
+    /// `CURVE_WIDTH` RGBA8 texels, each channel's output for the input at
+    /// that texel. Alpha is unused.
+    pub fn bake(&self) -> Vec<u8> {
+        (0..CURVE_WIDTH)
+            .flat_map(|x| {
+                let level = x as f32 / (CURVE_WIDTH - 1) as f32;
+                let [r, g, b] = self.apply([level; 3]).map(|c| (c * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[(f32, f32)]) -> Curve {
+        Curve::from(points.iter().map(|&(x, y)| CurvePoint::new(x, y)).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn stays_monotone_between_points() {
+        // a steep rise into a near flat span, where a plain spline overshoots
+        let curve = curve(&[(0.0, 0.0), (0.3, 0.6), (0.5, 0.62), (1.0, 1.0)]);
+        for pair in curve.points().windows(2) {
+            let mut last = pair[0].y;
+            for step in 0..=50 {
+                let y = curve.sample(pair[0].x + (pair[1].x - pair[0].x) * step as f32 / 50.0);
+                assert!(y >= last - 1e-6 && y <= pair[1].y + 1e-6, "{} after {}", y, last);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn passes_through_its_points() {
+        let through = curve(&[(0.0, 0.1), (0.4, 0.7), (1.0, 0.9)]);
+        for point in through.points() {
+            assert!((through.sample(point.x) - point.y).abs() < 1e-6);
+        }
+        // held flat past the ends
+        let short = curve(&[(0.2, 0.3), (0.8, 0.6)]);
+        assert_eq!(short.sample(0.0), 0.3);
+        assert_eq!(short.sample(1.0), 0.6);
+    }
+
+    #[test]
+    fn identity_bakes_to_a_ramp() {
+        let baked = Curves::default().bake();
+        assert_eq!(baked.len(), CURVE_WIDTH as usize * 4);
+        for (x, texel) in baked.chunks_exact(4).enumerate() {
+            assert_eq!(texel, [x as u8, x as u8, x as u8, 255]);
+        }
+        assert!(Curve::default().is_identity());
+    }
+
+    #[test]
+    fn clamps_saved_levels() {
+        let levels: Levels = serde_json::from_str(r#"{ "black": -1.0, "white": 2.0, "gamma": 50.0 }"#).unwrap();
+        assert_eq!(levels, Levels { black: 0.0, white: 1.0, gamma: 10.0 });
+
+        let crossed: Levels = serde_json::from_str(r#"{ "black": 0.8, "white": 0.2 }"#).unwrap();
+        assert_eq!(crossed.black, 0.8);
+        assert!(crossed.white >= crossed.black + MIN_RANGE);
+        assert_eq!(crossed.gamma, 1.0);
+    }
+
+    #[test]
+    fn caps_and_sorts_saved_curves() {
+        let points: Vec<CurvePoint> = (0..12).rev().map(|i| CurvePoint::new(i as f32 / 11.0, 0.5)).collect();
+        let json = serde_json::to_string(&points).unwrap();
+        let saved: Curve = serde_json::from_str(&json).unwrap();
+        assert_eq!(saved.points().len(), MAX_POINTS);
+        assert!(saved.points().windows(2).all(|pair| pair[0].x <= pair[1].x));
+
+        let single: Curve = serde_json::from_str(r#"[{ "x": 0.5, "y": 0.5 }]"#).unwrap();
+        assert_eq!(single, Curve::default());
+    }
+}
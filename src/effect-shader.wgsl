@@ -9,23 +9,93 @@ struct VertexOutput {
 };
 
 struct InputUniform {
-    effect: i32,
     fill_mode: i32,
     window_ratio: f32,
-    img_ratio: f32
+    img_ratio: f32,
+    padding: f32,
+};
+
+struct LayerUniform {
+    effect: i32,
+    follow_palette: i32,
+    tint: vec4<f32>,
+    shadow: vec4<f32>,
+    midtone: vec4<f32>,
+    intensity: f32,
+    threshold: f32,
+    offset: f32,
+    amount: f32,
+};
+
+// linear RGB, the alpha is always 1
+struct PaletteUniform {
+    bg: vec4<f32>,
+    fg: vec4<f32>,
+    accent: vec4<f32>,
+};
+
+struct AdjustmentUniform {
+    exposure: f32,
+    brightness: f32,
+    contrast: f32,
+    gamma: f32,
+    saturation: f32,
+    lut_intensity: f32,
+};
+
+// kind 0 is no LUT, 1 a 1D and 2 a 3D one, see `Packing` in lut.rs
+struct LutUniform {
+    domain_min: vec4<f32>,
+    domain_max: vec4<f32>,
+    kind: i32,
+    size: i32,
+    columns: i32,
+    padding: i32,
 };
 
 @group(1) @binding(0)
 var<uniform> input_uniform: InputUniform;
+@group(1) @binding(1)
+var<uniform> adjustment_uniform: AdjustmentUniform;
+@group(1) @binding(2)
+var<uniform> palette_uniform: PaletteUniform;
+
+// only bound for effect stack passes
+@group(2) @binding(0)
+var<uniform> layer_uniform: LayerUniform;
+// the layer's gradient, shadows on the left, GRADIENT_WIDTH x 1
+@group(2) @binding(1)
+var t_gradient: texture_2d<f32>;
+@group(2) @binding(2)
+var s_gradient: sampler;
+
+// levels and curves of each channel, indexed by display encoded level, CURVE_WIDTH x 1
+@group(3) @binding(0)
+var t_curves: texture_2d<f32>;
+@group(3) @binding(1)
+var s_curves: sampler;
+
+// only bound for the adjust pass, numbered after the layer bindings so they never collide
+@group(2) @binding(3)
+var<uniform> lut_uniform: LutUniform;
+// RGBA32F entries, read with textureLoad as float textures can't be filtered on WebGL2
+@group(2) @binding(4)
+var t_lut: texture_2d<f32>;
+
+// full-quad vertex stage for the passes that render into intermediate textures
+@vertex
+fn vs_pass(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = in.tex_coords;
+    out.position = vec4<f32>(in.pos, 0f, 1f);
+    return out;
+}
 
+// places the finished image on the surface according to the fill mode
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
-    if (input_uniform.effect == 1) { 
-        out.tex_coords = vec2<f32>(1.0f - in.tex_coords[0], in.tex_coords[1]); // flip horizontally
-    } else {
-        out.tex_coords = in.tex_coords;
-    }
+    out.tex_coords = in.tex_coords;
 
     // if taller than wide then shorten.
     // 0,0 becomes 0,0+x and 1,1 becomes 1,1-x
@@ -71,34 +141,195 @@ var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
 
+// exposure, brightness, contrast, gamma and saturation, in that order
+fn adjust(color: vec3<f32>) -> vec3<f32> {
+    var rgb = color * exp2(adjustment_uniform.exposure);
+    rgb = rgb + adjustment_uniform.brightness;
+    rgb = (rgb - 0.5) * adjustment_uniform.contrast + 0.5;
+    rgb = pow(max(rgb, vec3<f32>(0f)), vec3<f32>(1f / adjustment_uniform.gamma));
+    let luma = dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    rgb = mix(vec3<f32>(luma), rgb, adjustment_uniform.saturation);
+    return clamp(rgb, vec3<f32>(0f), vec3<f32>(1f));
+}
+
+// 5x5 box blur, `amount` is the radius in pixels
+fn blur(coords: vec2<f32>) -> vec4<f32> {
+    let texel = layer_uniform.amount / vec2<f32>(textureDimensions(t_diffuse));
+    var sum = vec4<f32>(0f);
+    for (var x: i32 = -2; x <= 2; x = x + 1) {
+        for (var y: i32 = -2; y <= 2; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * 0.5 * texel;
+            sum = sum + textureSampleLevel(t_diffuse, s_diffuse, coords + offset, 0f);
+        }
+    }
+    return sum / 25f;
+}
+
+// Rec. 709 luminance, textures are sampled in linear light
+fn luminance(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// linear light to the sRGB curve, where grey levels are spaced the way they look
+fn srgb_encode(c: f32) -> f32 {
+    if (c <= 0.0031308) {
+        return c * 12.92;
+    }
+    return 1.055 * pow(c, 1f / 2.4) - 0.055;
+}
+
+fn srgb_decode(c: f32) -> f32 {
+    if (c <= 0.04045) {
+        return c / 12.92;
+    }
+    return pow((c + 0.055) / 1.055, 2.4);
+}
+
+fn encode_rgb(rgb: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(srgb_encode(rgb.r), srgb_encode(rgb.g), srgb_encode(rgb.b));
+}
+
+fn decode_rgb(rgb: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(srgb_decode(rgb.r), srgb_decode(rgb.g), srgb_decode(rgb.b));
+}
+
+// through the texel centres so both ends are exact, like `gradient_map`
+fn apply_curves(rgb: vec3<f32>) -> vec3<f32> {
+    let width = f32(textureDimensions(t_curves).x);
+    let u = (clamp(rgb, vec3<f32>(0f), vec3<f32>(1f)) * (width - 1f) + 0.5) / width;
+    return vec3<f32>(
+        textureSample(t_curves, s_curves, vec2<f32>(u.r, 0.5)).r,
+        textureSample(t_curves, s_curves, vec2<f32>(u.g, 0.5)).g,
+        textureSample(t_curves, s_curves, vec2<f32>(u.b, 0.5)).b,
+    );
+}
+
+fn lut_entry_1d(i: i32) -> vec3<f32> {
+    return textureLoad(t_lut, vec2<i32>(i % lut_uniform.columns, i / lut_uniform.columns), 0).rgb;
+}
+
+// blue slices are tiled `columns` to a row
+fn lut_entry_3d(r: i32, g: i32, b: i32) -> vec3<f32> {
+    let n = lut_uniform.size;
+    let coords = vec2<i32>((b % lut_uniform.columns) * n + r, (b / lut_uniform.columns) * n + g);
+    return textureLoad(t_lut, coords, 0).rgb;
+}
+
+// looks up display encoded `rgb` with linear interpolation, the same as `Lut::apply`
+fn apply_lut(rgb: vec3<f32>) -> vec3<f32> {
+    let range = lut_uniform.domain_max.rgb - lut_uniform.domain_min.rgb;
+    let t = clamp((rgb - lut_uniform.domain_min.rgb) / range, vec3<f32>(0f), vec3<f32>(1f)) * f32(lut_uniform.size - 1);
+    let lower = min(vec3<i32>(floor(t)), vec3<i32>(lut_uniform.size - 2));
+    let f = t - vec3<f32>(lower);
+
+    if (lut_uniform.kind == 1) {
+        return vec3<f32>(
+            mix(lut_entry_1d(lower.r).r, lut_entry_1d(lower.r + 1).r, f.r),
+            mix(lut_entry_1d(lower.g).g, lut_entry_1d(lower.g + 1).g, f.g),
+            mix(lut_entry_1d(lower.b).b, lut_entry_1d(lower.b + 1).b, f.b),
+        );
+    }
+
+    let r = lower.r;
+    let g = lower.g;
+    let b = lower.b;
+    let near = mix(
+        mix(lut_entry_3d(r, g, b), lut_entry_3d(r + 1, g, b), f.r),
+        mix(lut_entry_3d(r, g + 1, b), lut_entry_3d(r + 1, g + 1, b), f.r),
+        f.g,
+    );
+    let far = mix(
+        mix(lut_entry_3d(r, g, b + 1), lut_entry_3d(r + 1, g, b + 1), f.r),
+        mix(lut_entry_3d(r, g + 1, b + 1), lut_entry_3d(r + 1, g + 1, b + 1), f.r),
+        f.g,
+    );
+    return mix(near, far, f.b);
+}
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var pixel: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let avg: f32 = (pixel.r + pixel.g + pixel.b) / 3f;
+fn fs_adjust(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sampled: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    // curves and LUTs are made for display encoded values, blended in the same space
+    var encoded = apply_curves(encode_rgb(adjust(sampled.rgb)));
+    if (lut_uniform.kind != 0) {
+        let graded = clamp(apply_lut(encoded), vec3<f32>(0f), vec3<f32>(1f));
+        encoded = mix(encoded, graded, adjustment_uniform.lut_intensity);
+    }
+    return vec4<f32>(decode_rgb(encoded), sampled.a);
+}
+
+// `grey` from shadows to highlights, through the texel centres so both ends are exact
+fn gradient_map(grey: f32) -> vec3<f32> {
+    let width = f32(textureDimensions(t_gradient).x);
+    let u = (clamp(grey, 0f, 1f) * (width - 1f) + 0.5) / width;
+    return textureSample(t_gradient, s_gradient, vec2<f32>(u, 0.5)).rgb;
+}
 
-    switch input_uniform.effect {
+fn noise(coords: vec2<f32>) -> f32 {
+    return fract(sin(dot(coords, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fs_effect(in: VertexOutput) -> @location(0) vec4<f32> {
+    var coords = in.tex_coords;
+    if (layer_uniform.effect == 1) {
+        coords = vec2<f32>(1f - coords.x, coords.y); // flip horizontally
+    }
+
+    let source: vec4<f32> = textureSample(t_diffuse, s_diffuse, coords);
+    var pixel: vec4<f32> = source;
+    let luma = luminance(pixel.rgb);
+    // coverage, thresholds and gradient positions go by how light the pixel looks
+    let grey = srgb_encode(luma);
+    var tint = layer_uniform.tint.rgb;
+    if (layer_uniform.follow_palette == 1) {
+        tint = palette_uniform.accent.rgb;
+    }
+
+    switch layer_uniform.effect {
         case 1: {
-            pixel = vec4<f32>(0.5f - avg, 0.5f - avg, 0.5f - avg, 1f); //inverted greyscale
+            let inverted = srgb_decode(clamp(layer_uniform.offset - grey, 0f, 1f));
+            pixel = vec4<f32>(inverted, inverted, inverted, 1f); //inverted greyscale
         }
         case 0: {
-            pixel = vec4<f32>(1f, 1f, 1f, avg); 
+            pixel = vec4<f32>(tint, grey); 
         }
         default {
             // regular image
         }
         case 2: {
-            pixel = vec4<f32>(0.86275, 0.79608, 0.89020, avg); 
+            pixel = vec4<f32>(tint, grey); 
         }
         case 3: {
-            pixel = vec4<f32>(0.3, avg, avg, 1f);
+            pixel = vec4<f32>(tint.r, luma * tint.g, luma * tint.b, 1f);
         }
         case 4: {
-            pixel = vec4<f32>(avg, avg, 1f, 1f);
+            pixel = vec4<f32>(luma * tint.r, luma * tint.g, tint.b, 1f);
         }
         case 5: {
-            pixel = vec4<f32>(0f, avg, 0f, 1f);
+            pixel = vec4<f32>(luma * tint, 1f);
+        }
+        case 7: {
+            pixel = blur(coords);
+        }
+        case 8, 10, 11: {
+            pixel = vec4<f32>(gradient_map(grey), 1f); // duotone, tritone, gradient map
         }
+        case 9: {
+            let grain = (noise(coords * vec2<f32>(textureDimensions(t_diffuse))) - 0.5) * layer_uniform.amount;
+            pixel = vec4<f32>(clamp(source.rgb + grain, vec3<f32>(0f), vec3<f32>(1f)), 1f);
+        }
+    }
+
+    if (grey < layer_uniform.threshold) {
+        pixel.a = 0f;
     }
 
-    return pixel;
-}
\ No newline at end of file
+    pixel = mix(source, pixel, layer_uniform.intensity);
+    return vec4<f32>(pixel.rgb, pixel.a * source.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+}
@@ -0,0 +1,443 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::color_management::ColorEntry;
+use crate::color_space;
+use crate::curves::{Curve, Curves, Levels};
+use crate::histogram::Channel;
+use crate::gradient::Gradient;
+
+pub const EFFECT_COUNT: usize = 12;
+pub const MAX_LAYERS: usize = 8;
+
+// order matches the `effect` switch in fs_effect
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    Mask,
+    Invert,
+    Tint,
+    Cyan,
+    Blue,
+    Green,
+    Original,
+    Blur,
+    Duotone,
+    Grain,
+    Tritone,
+    GradientMap,
+}
+
+impl Effect {
+    pub const ALL: [Effect; EFFECT_COUNT] = [
+        Effect::Mask,
+        Effect::Invert,
+        Effect::Tint,
+        Effect::Cyan,
+        Effect::Blue,
+        Effect::Green,
+        Effect::Original,
+        Effect::Blur,
+        Effect::Duotone,
+        Effect::Grain,
+        Effect::Tritone,
+        Effect::GradientMap,
+    ];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn next(&self) -> Effect {
+        Effect::ALL[(self.index() + 1) % EFFECT_COUNT]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Mask => "Mask",
+            Effect::Invert => "Invert",
+            Effect::Tint => "Tint",
+            Effect::Cyan => "Cyan",
+            Effect::Blue => "Blue",
+            Effect::Green => "Green",
+            Effect::Original => "Original",
+            Effect::Blur => "Blur",
+            Effect::Duotone => "Duotone",
+            Effect::Grain => "Grain",
+            Effect::Tritone => "Tritone",
+            Effect::GradientMap => "Gradient map",
+        }
+    }
+
+    /// The look each effect had when its colors were baked into the shader.
+    pub fn default_params(&self) -> EffectParams {
+        let tint = match self {
+            Effect::Mask => [1.0, 1.0, 1.0],
+            Effect::Tint => [0.86275, 0.79608, 0.89020],
+            Effect::Cyan => [0.3, 1.0, 1.0],
+            Effect::Blue => [1.0, 1.0, 1.0],
+            Effect::Green => [0.0, 1.0, 0.0],
+            Effect::Duotone => [0.27451, 1.0, 0.36471],
+            Effect::Tritone => [1.0, 0.92157, 0.69804],
+            _ => [1.0, 1.0, 1.0],
+        };
+        let amount = match self {
+            Effect::Blur => 4.0,
+            Effect::Grain => 0.15,
+            _ => 0.0,
+        };
+        EffectParams {
+            tint: [tint[0], tint[1], tint[2], 1.0],
+            shadow: [0.14510, 0.05490, 0.68235, 1.0],
+            midtone: default_midtone(),
+            intensity: 1.0,
+            threshold: 0.0,
+            offset: 0.5,
+            amount,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Pod, Zeroable, Serialize, Deserialize)]
+pub struct EffectParams {
+    pub tint: [f32; 4],
+    /// Dark end of the duotone and tritone ramps.
+    pub shadow: [f32; 4],
+    /// Middle of the tritone ramp.
+    #[serde(default = "default_midtone")]
+    pub midtone: [f32; 4],
+    pub intensity: f32,
+    pub threshold: f32,
+    pub offset: f32,
+    /// Blur radius in pixels, or grain strength.
+    pub amount: f32,
+}
+
+impl EffectParams {
+    pub fn set(&mut self, param: EffectParam) {
+        match param {
+            EffectParam::Intensity(v) => self.intensity = v.clamp(0.0, 1.0),
+            EffectParam::Threshold(v) => self.threshold = v.clamp(0.0, 1.0),
+            EffectParam::Offset(v) => self.offset = v,
+            EffectParam::Amount(v) => self.amount = v.max(0.0),
+            EffectParam::Tint(rgb) => self.tint = [rgb[0], rgb[1], rgb[2], 1.0],
+            EffectParam::Shadow(rgb) => self.shadow = [rgb[0], rgb[1], rgb[2], 1.0],
+            EffectParam::Midtone(rgb) => self.midtone = [rgb[0], rgb[1], rgb[2], 1.0],
+            // kept on `EffectSettings`, shared by every effect
+            EffectParam::Gradient(_) => {}
+        }
+    }
+
+    pub fn tint_hex(&self) -> String {
+        to_hex(&self.tint)
+    }
+
+    pub fn shadow_hex(&self) -> String {
+        to_hex(&self.shadow)
+    }
+
+    pub fn midtone_hex(&self) -> String {
+        to_hex(&self.midtone)
+    }
+}
+
+fn default_midtone() -> [f32; 4] {
+    [0.94118, 0.32157, 0.45882, 1.0]
+}
+
+fn to_hex(color: &[f32; 4]) -> String {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(color[0]), channel(color[1]), channel(color[2]))
+}
+
+/// A single named parameter change, sent from the UI with `FrontendEvent::SetEffectParam`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EffectParam {
+    /// Blend between the source pixel (0) and the full effect (1).
+    Intensity(f32),
+    /// Luminance below which the effect output is transparent.
+    Threshold(f32),
+    /// Grey level the inverted luminance is subtracted from.
+    Offset(f32),
+    /// Blur radius in pixels, or grain strength.
+    Amount(f32),
+    /// Color used by the tinted effects, as 0-1 sRGB.
+    Tint([f32; 3]),
+    /// Dark end of the duotone and tritone ramps, as 0-1 sRGB.
+    Shadow([f32; 3]),
+    /// Middle of the tritone ramp, as 0-1 sRGB.
+    Midtone([f32; 3]),
+    /// Stops of the gradient map.
+    Gradient(Gradient),
+}
+
+/// Active effect plus the tuned parameters of every effect, so stepping
+/// through effects doesn't lose adjustments.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EffectSettings {
+    pub effect: Effect,
+    #[serde(deserialize_with = "deserialize_params")]
+    params: [EffectParams; EFFECT_COUNT],
+    #[serde(default)]
+    gradient: Gradient,
+}
+
+// settings saved before an effect was added have fewer entries, the new
+// effects start from their defaults
+fn deserialize_params<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[EffectParams; EFFECT_COUNT], D::Error> {
+    let saved = Vec::<EffectParams>::deserialize(deserializer)?;
+    let mut params = Effect::ALL.map(|effect| effect.default_params());
+    for (slot, saved) in params.iter_mut().zip(saved) {
+        *slot = saved;
+    }
+    Ok(params)
+}
+
+impl EffectSettings {
+    pub fn new() -> Self {
+        Self::with_effect(Effect::Mask)
+    }
+
+    pub fn with_effect(effect: Effect) -> Self {
+        EffectSettings {
+            effect,
+            params: Effect::ALL.map(|effect| effect.default_params()),
+            gradient: Gradient::default(),
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.effect = self.effect.next();
+    }
+
+    pub fn set(&mut self, param: EffectParam) {
+        match param {
+            EffectParam::Gradient(gradient) => self.gradient = gradient,
+            _ => self.params[self.effect.index()].set(param),
+        }
+    }
+
+    pub fn current(&self) -> &EffectParams {
+        &self.params[self.effect.index()]
+    }
+
+    pub fn gradient(&self) -> &Gradient {
+        &self.gradient
+    }
+}
+
+impl Default for EffectSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EffectLayer {
+    pub settings: EffectSettings,
+    pub enabled: bool,
+    /// Take the tint and shadow from the active palette's accent and
+    /// background instead of the layer's own colors.
+    #[serde(default)]
+    pub follow_palette: bool,
+}
+
+impl EffectLayer {
+    /// What the gradient effects map luminance onto, `None` for the rest.
+    /// Following the palette swaps in the colors of `palette`.
+    pub fn gradient(&self, palette: &ColorEntry) -> Option<Gradient> {
+        let params = self.settings.current();
+        let rgb = |color: [f32; 4]| [color[0], color[1], color[2]];
+        let hex = |color| color_space::from_hex(color).map(|c| c as f32);
+        match (self.settings.effect, self.follow_palette) {
+            (Effect::Duotone, false) => Some(Gradient::duotone(rgb(params.shadow), rgb(params.tint))),
+            (Effect::Duotone, true) => Some(Gradient::duotone(hex(palette.bg_rgb), hex(palette.accent_rgb()))),
+            (Effect::Tritone, false) => Some(Gradient::tritone(rgb(params.shadow), rgb(params.midtone), rgb(params.tint))),
+            (Effect::GradientMap, false) => Some(*self.settings.gradient()),
+            (Effect::Tritone | Effect::GradientMap, true) => Some(Gradient::from_entry(palette)),
+            _ => None,
+        }
+    }
+}
+
+/// Ordered effects, applied bottom (index 0) to top, each rendering into
+/// its own intermediate texture. Out of range indices are ignored so a UI
+/// that is one event behind can't panic the renderer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<EffectLayer>", into = "Vec<EffectLayer>")]
+pub struct EffectStack {
+    layers: Vec<EffectLayer>,
+}
+
+impl EffectStack {
+    pub fn new() -> Self {
+        let mut stack = EffectStack { layers: Vec::new() };
+        stack.add(Effect::Mask);
+        stack
+    }
+
+    pub fn layers(&self) -> &[EffectLayer] {
+        &self.layers
+    }
+
+    pub fn get(&self, index: usize) -> Option<&EffectLayer> {
+        self.layers.get(index)
+    }
+
+    pub fn add(&mut self, effect: Effect) {
+        if self.layers.len() < MAX_LAYERS {
+            self.layers.push(EffectLayer {
+                settings: EffectSettings::with_effect(effect),
+                enabled: true,
+                follow_palette: false,
+            });
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.layers.remove(index);
+        }
+    }
+
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        if from < self.layers.len() && to < self.layers.len() {
+            let layer = self.layers.remove(from);
+            self.layers.insert(to, layer);
+        }
+    }
+
+    pub fn toggle(&mut self, index: usize) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.enabled = !layer.enabled;
+        }
+    }
+
+    pub fn toggle_palette(&mut self, index: usize) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.follow_palette = !layer.follow_palette;
+        }
+    }
+
+    pub fn step(&mut self, index: usize) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.settings.step();
+        }
+    }
+
+    pub fn set(&mut self, index: usize, param: EffectParam) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.settings.set(param);
+        }
+    }
+}
+
+impl Default for EffectStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Vec<EffectLayer>> for EffectStack {
+    fn from(mut layers: Vec<EffectLayer>) -> Self {
+        layers.truncate(MAX_LAYERS);
+        EffectStack { layers }
+    }
+}
+
+impl From<EffectStack> for Vec<EffectLayer> {
+    fn from(stack: EffectStack) -> Self {
+        stack.layers
+    }
+}
+
+// uploaded as an AdjustmentUniform, with the curves baked into a texture
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Adjustments {
+    /// Stops of exposure, applied as a `2^exposure` multiplier in linear light.
+    pub exposure: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    pub saturation: f32,
+    /// Blend between the adjusted image (0) and the loaded LUT's grade (1).
+    #[serde(default = "default_lut_intensity")]
+    pub lut_intensity: f32,
+    /// Levels and curves, after the adjustments above and before the LUT.
+    #[serde(default)]
+    pub curves: Curves,
+}
+
+fn default_lut_intensity() -> f32 {
+    1.0
+}
+
+impl Adjustments {
+    pub fn new() -> Self {
+        Adjustments {
+            exposure: 0.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+            lut_intensity: default_lut_intensity(),
+            curves: Curves::default(),
+        }
+    }
+
+    /// The sliders on a linear RGB color, the same as `adjust` in
+    /// effect-shader.wgsl. The curves aren't included, they work on
+    /// display encoded values.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let rgb = rgb.map(|c| {
+            let c = c * self.exposure.exp2() + self.brightness;
+            let c = (c - 0.5) * self.contrast + 0.5;
+            c.max(0.0).powf(1.0 / self.gamma)
+        });
+        let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        rgb.map(|c| (luma + (c - luma) * self.saturation).clamp(0.0, 1.0))
+    }
+
+    pub fn set(&mut self, adjustment: Adjustment) {
+        match adjustment {
+            Adjustment::Exposure(v) => self.exposure = v.clamp(-5.0, 5.0),
+            Adjustment::Brightness(v) => self.brightness = v.clamp(-1.0, 1.0),
+            Adjustment::Contrast(v) => self.contrast = v.max(0.0),
+            // gamma of 0 would divide by zero in the shader
+            Adjustment::Gamma(v) => self.gamma = v.max(0.01),
+            Adjustment::Saturation(v) => self.saturation = v.max(0.0),
+            Adjustment::LutIntensity(v) => self.lut_intensity = v.clamp(0.0, 1.0),
+            Adjustment::Levels(channel, levels) => self.curves.set_levels(channel, levels),
+            Adjustment::Curve(channel, curve) => self.curves.set_curve(channel, curve),
+        }
+    }
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single tonal adjustment, sent from the UI with `FrontendEvent::SetAdjustment`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Adjustment {
+    Exposure(f32),
+    Brightness(f32),
+    Contrast(f32),
+    Gamma(f32),
+    Saturation(f32),
+    LutIntensity(f32),
+    Levels(Channel, Levels),
+    Curve(Channel, Curve),
+}
+
+impl Adjustment {
+    /// The channel a levels or curve edit is for.
+    pub fn channel(&self) -> Option<Channel> {
+        match self {
+            Adjustment::Levels(channel, _) | Adjustment::Curve(channel, _) => Some(*channel),
+            _ => None,
+        }
+    }
+}
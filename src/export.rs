@@ -0,0 +1,201 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{ColorType, DynamicImage, ImageError, ImageOutputFormat, RgbaImage};
+
+use crate::image_decode::download_bytes;
+use crate::metadata::embed_exif;
+use crate::overlay::Overlay;
+use crate::render_pipeline::WebImage;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExportFormat {
+    Png,
+    /// Quality from 1 to 100.
+    Jpeg(u8),
+    /// Lossless, the lossy encoder needs libwebp which doesn't build for wasm.
+    WebP,
+    /// 16 bits per channel. The render target is 8 bit, so samples are
+    /// widened rather than carrying extra precision.
+    Tiff16,
+}
+
+impl ExportFormat {
+    pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG",
+            ExportFormat::Jpeg(_) => "JPEG",
+            ExportFormat::WebP => "WebP",
+            ExportFormat::Tiff16 => "TIFF",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg(_) => "jpg",
+            ExportFormat::WebP => "webp",
+            ExportFormat::Tiff16 => "tiff",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "image/png",
+            ExportFormat::Jpeg(_) => "image/jpeg",
+            ExportFormat::WebP => "image/webp",
+            ExportFormat::Tiff16 => "image/tiff",
+        }
+    }
+
+    /// Whether source EXIF can be written back into this format.
+    pub fn supports_metadata(&self) -> bool {
+        !matches!(self, ExportFormat::Tiff16)
+    }
+}
+
+/// Output size, format and name of an export. Leaving both sides unset
+/// renders at the source image's resolution; setting only one keeps the
+/// source aspect ratio.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: ExportFormat,
+    pub file_name: String,
+    /// Re-embed the source image's EXIF, otherwise the export carries none.
+    pub keep_metadata: bool,
+    /// Panels to draw over the render. When set the export is framed like
+    /// the canvas rather than cropped to the image.
+    pub overlay: Option<Overlay>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            width: None,
+            height: None,
+            format: ExportFormat::Png,
+            file_name: file_name(None, &[], ExportFormat::Png),
+            keep_metadata: true,
+            overlay: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn resolve_size(&self, source: (u32, u32), max: u32) -> (u32, u32) {
+        let (src_width, src_height) = (source.0 as f64, source.1 as f64);
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width as f64, height as f64),
+            (Some(width), None) => (width as f64, width as f64 * src_height / src_width),
+            (None, Some(height)) => (height as f64 * src_width / src_height, height as f64),
+            (None, None) => (src_width, src_height),
+        };
+
+        // scale down uniformly rather than clamping one side and distorting
+        let scale = (max as f64 / width.max(height)).min(1.0);
+        (
+            ((width * scale).round() as u32).max(1),
+            ((height * scale).round() as u32).max(1),
+        )
+    }
+}
+
+/// `photo.heic` with a blur and grain stack becomes `photo-blur-grain.png`,
+/// and a gradient map adds `gradient-map`.
+pub fn file_name(source: Option<&str>, effects: &[&str], format: ExportFormat) -> String {
+    let stem = source
+        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("render");
+    let effects = if effects.is_empty() {
+        String::from("original")
+    } else {
+        effects.iter()
+            .flat_map(|name| name.split_whitespace())
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase()
+    };
+    format!("{}-{}.{}", stem, effects, format.extension())
+}
+
+pub fn encode(img: &WebImage, format: ExportFormat) -> Result<Vec<u8>, ImageError> {
+    let buffer = RgbaImage::from_raw(img.width, img.height, img.data.clone())
+        .ok_or_else(|| ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)))?;
+    let mut bytes = Cursor::new(Vec::new());
+
+    match format {
+        ExportFormat::Png => {
+            DynamicImage::ImageRgba8(buffer).write_to(&mut bytes, ImageOutputFormat::Png)?;
+        }
+        ExportFormat::Jpeg(quality) => {
+            // the background is already composited in, so dropping alpha loses nothing
+            let rgb = DynamicImage::ImageRgba8(buffer).into_rgb8();
+            JpegEncoder::new_with_quality(&mut bytes, quality.clamp(1, 100))
+                .encode(&rgb, img.width, img.height, ColorType::Rgb8)?;
+        }
+        ExportFormat::WebP => {
+            WebPEncoder::new_lossless(&mut bytes)
+                .encode(&buffer, img.width, img.height, ColorType::Rgba8)?;
+        }
+        ExportFormat::Tiff16 => {
+            let wide = DynamicImage::ImageRgba16(DynamicImage::ImageRgba8(buffer).into_rgba16());
+            wide.write_to(&mut bytes, ImageOutputFormat::Tiff)?;
+        }
+    }
+    Ok(bytes.into_inner())
+}
+
+pub fn save(img: &WebImage, options: &ExportOptions) {
+    match encode(img, options.format) {
+        Ok(bytes) => {
+            let bytes = match (&img.metadata.exif, options.keep_metadata) {
+                (Some(exif), true) => embed_exif(bytes, options.format, exif),
+                _ => bytes,
+            };
+            download_bytes(&bytes, options.format.mime(), &options.file_name)
+        }
+        Err(e) => log::error!("couldn't encode export: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ImageMetadata;
+
+    fn options(width: Option<u32>, height: Option<u32>) -> ExportOptions {
+        ExportOptions { width, height, ..ExportOptions::default() }
+    }
+
+    #[test]
+    fn resolves_the_size() {
+        assert_eq!(options(None, None).resolve_size((400, 300), 4096), (400, 300));
+        assert_eq!(options(Some(200), None).resolve_size((400, 300), 4096), (200, 150));
+        assert_eq!(options(None, Some(600)).resolve_size((400, 300), 4096), (800, 600));
+        assert_eq!(options(Some(100), Some(100)).resolve_size((400, 300), 4096), (100, 100));
+        // past the limit both sides shrink, keeping the shape
+        assert_eq!(options(Some(8000), None).resolve_size((400, 300), 4000), (4000, 3000));
+        assert_eq!(options(Some(1), None).resolve_size((4000, 10), 4096), (1, 1));
+    }
+
+    #[test]
+    fn names_exports_after_the_image_and_effects() {
+        assert_eq!(file_name(Some("photo.heic"), &["Blur", "Grain"], ExportFormat::Png), "photo-blur-grain.png");
+        assert_eq!(file_name(Some("photo.heic"), &["Gradient Map"], ExportFormat::Jpeg(90)), "photo-gradient-map.jpg");
+        assert_eq!(file_name(Some("archive.tar.gz"), &[], ExportFormat::WebP), "archive.tar-original.webp");
+        assert_eq!(file_name(None, &[], ExportFormat::Tiff16), "render-original.tiff");
+    }
+
+    #[test]
+    fn refuses_a_buffer_of_the_wrong_size() {
+        let img = WebImage { width: 2, height: 2, data: vec![0; 12], metadata: ImageMetadata::default() };
+        assert!(matches!(encode(&img, ExportFormat::Png), Err(ImageError::Parameter(_))));
+    }
+}
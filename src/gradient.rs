@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+use crate::color_management::ColorEntry;
+use crate::color_space;
+use crate::point_list::{Point, PointList};
+
+/// Most stops a gradient holds.
+pub const MAX_STOPS: usize = 8;
+/// Texels in a baked gradient, the width of its lookup texture.
+pub const GRADIENT_WIDTH: u32 = 256;
+
+/// A color along a gradient, as 0-1 sRGB like the effect tints.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    /// 0 for the shadows to 1 for the highlights.
+    pub position: f32,
+    pub color: [f32; 3],
+}
+
+impl GradientStop {
+    pub fn new(position: f32, color: [f32; 3]) -> Self {
+        GradientStop { position: position.clamp(0.0, 1.0), color }
+    }
+
+    pub fn hex(&self) -> String {
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        format!("#{:02x}{:02x}{:02x}", channel(self.color[0]), channel(self.color[1]), channel(self.color[2]))
+    }
+}
+
+impl Point for GradientStop {
+    fn position(&self) -> f32 {
+        self.position
+    }
+
+    fn with_position(self, position: f32) -> Self {
+        GradientStop::new(position, self.color)
+    }
+}
+
+/// Colors luminance is mapped onto, from 2 to `MAX_STOPS` stops kept in
+/// position order. Blends between stops in OKLab so the ramp looks even.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<GradientStop>", into = "Vec<GradientStop>")]
+pub struct Gradient {
+    stops: PointList<GradientStop, MAX_STOPS>,
+}
+
+impl Gradient {
+    /// `colors` spread evenly from shadows to highlights.
+    pub fn even(colors: &[[f32; 3]]) -> Self {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        Gradient::from(colors.iter()
+            .enumerate()
+            .map(|(i, &color)| GradientStop::new(i as f32 / last, color))
+            .collect::<Vec<_>>())
+    }
+
+    pub fn duotone(shadow: [f32; 3], highlight: [f32; 3]) -> Self {
+        Gradient::even(&[shadow, highlight])
+    }
+
+    pub fn tritone(shadow: [f32; 3], midtone: [f32; 3], highlight: [f32; 3]) -> Self {
+        Gradient::even(&[shadow, midtone, highlight])
+    }
+
+    /// The entry's background, accent and foreground, darkest first.
+    pub fn from_entry(entry: &ColorEntry) -> Self {
+        let mut colors = [entry.bg_rgb, entry.accent_rgb(), entry.fg_rgb].map(color_space::from_hex);
+        colors.sort_by(|a, b| {
+            let luminance = |rgb: &[f64; 3]| color_space::luminance(color_space::srgb_to_linear_rgb(*rgb));
+            luminance(a).total_cmp(&luminance(b))
+        });
+        Gradient::even(&colors.map(|rgb| rgb.map(|c| c as f32)))
+    }
+
+    pub fn stops(&self) -> &[GradientStop] {
+        self.stops.points()
+    }
+
+    /// Replaces the stop at `index`, kept between its neighbours.
+    pub fn set(&mut self, index: usize, stop: GradientStop) {
+        self.stops.set(index, stop);
+    }
+
+    /// Splits the widest gap between stops with its midpoint color.
+    pub fn add_stop(&mut self) {
+        if let Some(position) = self.stops.widest_gap() {
+            self.stops.insert(GradientStop::new(position, self.sample(position)));
+        }
+    }
+
+    pub fn remove_stop(&mut self, index: usize) {
+        self.stops.remove(index);
+    }
+
+    /// Color at `position`, held flat past the first and last stops.
+    pub fn sample(&self, position: f32) -> [f32; 3] {
+        let stops = self.stops();
+        let (from, to) = match self.stops.span(position) {
+            Ok(i) => (stops[i], stops[i + 1]),
+            Err(end) => return end.color,
+        };
+        let span = to.position - from.position;
+        let t = if span > 0.0 { ((position - from.position) / span) as f64 } else { 1.0 };
+        // exactly on a stop, its color without a trip through OKLab
+        if t >= 1.0 {
+            return to.color;
+        }
+
+        let [a, b] = [from.color, to.color].map(|color| color_space::srgb_to_oklab(color.map(f64::from)));
+        let mixed = [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+        color_space::oklab_to_srgb(mixed).map(|c| c as f32)
+    }
+
+    /// `GRADIENT_WIDTH` sRGB RGBA8 texels, shadows first.
+    pub fn bake(&self) -> Vec<u8> {
+        (0..GRADIENT_WIDTH)
+            .flat_map(|x| {
+                let [r, g, b] = self.sample(x as f32 / (GRADIENT_WIDTH - 1) as f32)
+                    .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+
+    /// The stops as a CSS gradient, for previews.
+    pub fn css(&self) -> String {
+        let stops: Vec<String> = self.stops().iter()
+            .map(|stop| format!("{} {:.1}%", stop.hex(), stop.position * 100.0))
+            .collect();
+        format!("linear-gradient(in oklab to right, {})", stops.join(", "))
+    }
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Gradient::tritone([0.14510, 0.05490, 0.68235], [0.94118, 0.32157, 0.45882], [1.0, 0.92157, 0.69804])
+    }
+}
+
+impl From<Vec<GradientStop>> for Gradient {
+    fn from(stops: Vec<GradientStop>) -> Self {
+        let stops: Vec<GradientStop> = match stops[..] {
+            // a flat ramp, the list needs both ends
+            [only] => vec![GradientStop::new(0.0, only.color), GradientStop::new(1.0, only.color)],
+            _ => stops.iter().map(|stop| GradientStop::new(stop.position, stop.color)).collect(),
+        };
+        PointList::new(&stops).map(|stops| Gradient { stops }).unwrap_or_default()
+    }
+}
+
+impl From<Gradient> for Vec<GradientStop> {
+    fn from(gradient: Gradient) -> Self {
+        gradient.stops().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADOW: [f32; 3] = [0.1, 0.2, 0.3];
+    const MIDTONE: [f32; 3] = [0.9, 0.4, 0.2];
+    const HIGHLIGHT: [f32; 3] = [0.95, 0.9, 0.7];
+
+    fn texel(baked: &[u8], x: usize) -> [u8; 4] {
+        [baked[x * 4], baked[x * 4 + 1], baked[x * 4 + 2], baked[x * 4 + 3]]
+    }
+
+    fn rgba8(color: [f32; 3]) -> [u8; 4] {
+        let [r, g, b] = color.map(|c| (c * 255.0).round() as u8);
+        [r, g, b, 255]
+    }
+
+    #[test]
+    fn stops_keep_their_colors() {
+        let gradient = Gradient::tritone(SHADOW, MIDTONE, HIGHLIGHT);
+        assert_eq!(gradient.sample(0.0), SHADOW);
+        assert_eq!(gradient.sample(0.5), MIDTONE);
+        assert_eq!(gradient.sample(1.0), HIGHLIGHT);
+
+        let baked = gradient.bake();
+        assert_eq!(baked.len(), GRADIENT_WIDTH as usize * 4);
+        assert_eq!(texel(&baked, 0), rgba8(SHADOW));
+        assert_eq!(texel(&baked, GRADIENT_WIDTH as usize - 1), rgba8(HIGHLIGHT));
+    }
+
+    #[test]
+    fn holds_flat_past_the_ends() {
+        let gradient = Gradient::from(vec![GradientStop::new(0.25, SHADOW), GradientStop::new(0.75, HIGHLIGHT)]);
+        assert_eq!(gradient.sample(0.0), SHADOW);
+        assert_eq!(gradient.sample(1.0), HIGHLIGHT);
+    }
+
+    #[test]
+    fn one_stop_is_a_flat_ramp() {
+        let baked = Gradient::even(&[MIDTONE]).bake();
+        assert!(baked.chunks_exact(4).all(|texel| texel == rgba8(MIDTONE)));
+    }
+
+    #[test]
+    fn adds_stops_on_the_ramp() {
+        let mut gradient = Gradient::duotone(SHADOW, HIGHLIGHT);
+        let middle = gradient.sample(0.5);
+        gradient.add_stop();
+        assert_eq!(gradient.stops().len(), 3);
+        assert_eq!(gradient.stops()[1], GradientStop::new(0.5, middle));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::color_space::srgb_to_linear;
+
+/// One bin per 8 bit level.
+pub const BINS: usize = 256;
+/// Longest side the rendered output is scaled to before it's counted.
+pub const OUTPUT_SIZE: u32 = 512;
+
+/// A color channel, or all three together. Histograms count the
+/// luminance for `Rgb`, levels and curves apply it to each color.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channel {
+    Rgb,
+    Red,
+    Green,
+    Blue,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [Channel::Rgb, Channel::Red, Channel::Green, Channel::Blue];
+
+    /// The channel after this one, back to RGB after blue.
+    pub fn next(&self) -> Channel {
+        Channel::ALL[(self.index() + 1) % Channel::ALL.len()]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Rgb => "RGB",
+            Channel::Red => "Red",
+            Channel::Green => "Green",
+            Channel::Blue => "Blue",
+        }
+    }
+
+    /// What the channel is drawn in.
+    pub fn css(&self) -> &'static str {
+        match self {
+            Channel::Rgb => "rgb(200, 200, 200)",
+            Channel::Red => "rgb(230, 60, 60)",
+            Channel::Green => "rgb(60, 200, 90)",
+            Channel::Blue => "rgb(70, 120, 240)",
+        }
+    }
+
+    /// Where the channel's values are kept, in `ALL` order.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Summary of one channel, levels are 0-255.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelStats {
+    pub mean: f32,
+    pub median: u8,
+    pub min: u8,
+    pub max: u8,
+    /// Share of pixels at 0, crushed shadows.
+    pub clipped_low: f32,
+    /// Share of pixels at 255, blown highlights.
+    pub clipped_high: f32,
+}
+
+/// Pixel counts per level for luminance and each color channel.
+/// Transparent pixels aren't counted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    // boxed, every session image carries one
+    counts: Box<[[u32; BINS]; 4]>,
+    total: u32,
+}
+
+impl Histogram {
+    /// Counts RGBA8 sRGB pixels, as in `WebImage::data`.
+    pub fn from_rgba(data: &[u8]) -> Self {
+        // the linear value halfway between each level and the next, so
+        // luminance is binned without converting every pixel back
+        let levels: Vec<f64> = (0..BINS).map(|level| srgb_to_linear(level as f64 / 255.0)).collect();
+        let bounds: Vec<f64> = levels.windows(2).map(|pair| (pair[0] + pair[1]) / 2.0).collect();
+
+        let mut histogram = Histogram { counts: Box::new([[0; BINS]; 4]), total: 0 };
+        for px in data.chunks_exact(4).filter(|px| px[3] > 0) {
+            let [r, g, b] = [px[0], px[1], px[2]].map(|c| levels[c as usize]);
+            let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let bin = bounds.partition_point(|&bound| bound < luminance);
+
+            histogram.counts[Channel::Rgb.index()][bin] += 1;
+            histogram.counts[Channel::Red.index()][px[0] as usize] += 1;
+            histogram.counts[Channel::Green.index()][px[1] as usize] += 1;
+            histogram.counts[Channel::Blue.index()][px[2] as usize] += 1;
+            histogram.total += 1;
+        }
+        histogram
+    }
+
+    pub fn counts(&self, channel: Channel) -> &[u32; BINS] {
+        &self.counts[channel.index()]
+    }
+
+    /// `None` when nothing was counted.
+    pub fn stats(&self, channel: Channel) -> Option<ChannelStats> {
+        if self.total == 0 {
+            return None;
+        }
+        let counts = self.counts(channel);
+        let total = self.total as f64;
+        let occupied = |level: &usize| counts[*level] > 0;
+        let sum: f64 = counts.iter().enumerate().map(|(level, &count)| level as f64 * count as f64).sum();
+
+        let mut seen = 0;
+        let median = counts.iter()
+            .position(|&count| {
+                seen += count;
+                seen * 2 >= self.total
+            })
+            .unwrap_or(0) as u8;
+
+        Some(ChannelStats {
+            mean: (sum / total) as f32,
+            median,
+            min: (0..BINS).find(occupied).unwrap_or(0) as u8,
+            max: (0..BINS).rev().find(occupied).unwrap_or(0) as u8,
+            clipped_low: (counts[0] as f64 / total) as f32,
+            clipped_high: (counts[BINS - 1] as f64 / total) as f32,
+        })
+    }
+
+    /// Every channel as an SVG, luminance filled and the colors outlined.
+    /// Heights are square rooted so a spike doesn't flatten everything else.
+    pub fn svg(&self) -> String {
+        let height = 100.0;
+        let mut svg = format!("<svg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 {} {}' preserveAspectRatio='none'>", BINS, height);
+        for channel in Channel::ALL {
+            let counts = self.counts(channel);
+            let peak = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+            let points: Vec<String> = counts.iter()
+                .enumerate()
+                .map(|(level, &count)| format!("{} {:.1}", level, height - (count as f64 / peak).sqrt() * height))
+                .collect();
+            let style = match channel {
+                Channel::Rgb => format!("fill='{}' stroke='none'", channel.css()),
+                _ => format!("fill='none' stroke='{}' stroke-width='1.5' vector-effect='non-scaling-stroke'", channel.css()),
+            };
+            svg.push_str(&format!("<path d='M0 {h} L{} L{} {h} Z' {}/>", points.join(" L"), BINS - 1, style, h = height));
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    /// `svg` as a data URL for an `img`.
+    pub fn data_url(&self) -> String {
+        svg_data_url(&self.svg())
+    }
+}
+
+/// `svg` as a data URL, escaped just enough for an `img` source.
+pub fn svg_data_url(svg: &str) -> String {
+    let escaped = svg.replace('<', "%3C").replace('>', "%3E").replace('#', "%23");
+    format!("data:image/svg+xml;utf8,{}", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // black, white, red and mid gray, then a transparent pixel that isn't counted
+    const PIXELS: [u8; 20] = [
+        0, 0, 0, 255,
+        255, 255, 255, 255,
+        255, 0, 0, 255,
+        100, 100, 100, 255,
+        50, 50, 50, 0,
+    ];
+
+    #[test]
+    fn bins_each_channel() {
+        let histogram = Histogram::from_rgba(&PIXELS);
+        let red = histogram.counts(Channel::Red);
+        assert_eq!((red[0], red[100], red[255]), (1, 1, 2));
+        assert_eq!(red.iter().sum::<u32>(), 4);
+        assert_eq!(histogram.counts(Channel::Green)[0], 2);
+        assert_eq!(histogram.counts(Channel::Blue)[50], 0);
+
+        // gray stays at its level, pure red lands where its luminance encodes
+        let luminance = histogram.counts(Channel::Rgb);
+        assert_eq!((luminance[0], luminance[100], luminance[127], luminance[255]), (1, 1, 1, 1));
+    }
+
+    #[test]
+    fn summarises_each_channel() {
+        let histogram = Histogram::from_rgba(&PIXELS);
+        assert_eq!(histogram.stats(Channel::Red), Some(ChannelStats {
+            mean: 152.5,
+            median: 100,
+            min: 0,
+            max: 255,
+            clipped_low: 0.25,
+            clipped_high: 0.5,
+        }));
+        assert_eq!(histogram.stats(Channel::Green), Some(ChannelStats {
+            mean: 88.75,
+            median: 0,
+            min: 0,
+            max: 255,
+            clipped_low: 0.5,
+            clipped_high: 0.25,
+        }));
+    }
+
+    #[test]
+    fn has_no_stats_without_pixels() {
+        assert_eq!(Histogram::from_rgba(&[]).stats(Channel::Rgb), None);
+        assert_eq!(Histogram::from_rgba(&[10, 20, 30, 0]).stats(Channel::Red), None);
+    }
+}
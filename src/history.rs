@@ -0,0 +1,353 @@
+use std::mem::Discriminant;
+
+use crate::color_management::PaletteSource;
+use crate::histogram::Channel;
+use crate::effects::{Adjustment, Adjustments, EffectParam, EffectStack};
+use crate::lut::Lut;
+use crate::session::SessionImage;
+
+/// Most steps kept for undo.
+pub const MAX_STEPS: usize = 200;
+/// Most image loads kept for undo. An undone load keeps its pixels in the
+/// renderer so it can be redone, this bounds how many are held that way.
+pub const MAX_IMAGE_STEPS: usize = 8;
+
+/// One undoable edit, recorded alongside the `FrontendEvent` it sent.
+/// Adjustments and images are boxed, they're far larger than the other steps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Any change to the effect stack. `merge` is set for slider edits so a
+    /// drag becomes one step rather than one per input event, until
+    /// `History::end_merge` ends the drag.
+    Effects {
+        before: EffectStack,
+        after: EffectStack,
+        merge: Option<(usize, Discriminant<EffectParam>)>,
+    },
+    /// `merge` is set for slider and curve point drags, like `Effects`.
+    Adjustments {
+        before: Box<Adjustments>,
+        after: Box<Adjustments>,
+        merge: Option<AdjustmentKey>,
+    },
+    /// Toggling the fill mode is its own inverse.
+    FillMode,
+    /// Switching palettes or cycling through one.
+    Palette {
+        before: PaletteState,
+        after: PaletteState,
+    },
+    /// Importing or removing the active image's LUT.
+    Lut {
+        before: Option<Lut>,
+        after: Option<Lut>,
+    },
+    /// `image` was appended to the session while `previous` (a session
+    /// index) was active.
+    LoadImage {
+        image: Box<SessionImage>,
+        previous: Option<usize>,
+    },
+    /// Switched the active image between two session indices.
+    SelectImage {
+        from: Option<usize>,
+        to: usize,
+    },
+}
+
+/// The control an adjustment came from. Levels and curves have one set
+/// of controls for every channel, and a curve one per point.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdjustmentKey {
+    pub adjustment: Discriminant<Adjustment>,
+    pub channel: Option<Channel>,
+    pub point: Option<usize>,
+}
+
+/// The palette settings that pick the color sent to the renderer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PaletteState {
+    pub source: PaletteSource,
+    pub color_index: usize,
+    pub readable: bool,
+}
+
+impl AdjustmentKey {
+    pub fn new(adjustment: &Adjustment, point: Option<usize>) -> Self {
+        AdjustmentKey {
+            adjustment: std::mem::discriminant(adjustment),
+            channel: adjustment.channel(),
+            point,
+        }
+    }
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Effects { .. } => "Effects",
+            Command::Adjustments { .. } => "Light",
+            Command::FillMode => "Fill",
+            Command::Palette { .. } => "Palette",
+            Command::Lut { .. } => "LUT",
+            Command::LoadImage { .. } => "Load",
+            Command::SelectImage { .. } => "Select",
+        }
+    }
+
+    // folds `next` into `self` when both are steps of the same slider, the
+    // history decides whether they're still the same drag
+    fn merge(&mut self, next: &Command) -> bool {
+        match (self, next) {
+            (
+                Command::Effects { after, merge: Some(key), .. },
+                Command::Effects { after: next_after, merge: Some(next_key), .. },
+            ) if key == next_key => {
+                *after = next_after.clone();
+                true
+            }
+            (
+                Command::Adjustments { after, merge: Some(key), .. },
+                Command::Adjustments { after: next_after, merge: Some(next_key), .. },
+            ) if key == next_key => {
+                **after = **next_after;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    // the last step is finished, the next edit is a step of its own
+    sealed: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Records an edit that has already been applied. Returns the images
+    /// that can no longer be redone, whose pixels the renderer can free.
+    pub fn record(&mut self, command: Command) -> Vec<SessionImage> {
+        let released = self.drain_redo();
+
+        let merged = !self.sealed && self.undo.last_mut().is_some_and(|last| last.merge(&command));
+        self.sealed = false;
+        if !merged {
+            self.undo.push(command);
+        }
+
+        // dropping from the bottom keeps the remaining steps consistent,
+        // every one of them still applies to the state left by the one before
+        let loads = self.undo.iter().filter(|c| matches!(c, Command::LoadImage { .. })).count();
+        if loads > MAX_IMAGE_STEPS {
+            if let Some(oldest) = self.undo.iter().position(|c| matches!(c, Command::LoadImage { .. })) {
+                self.undo.drain(..=oldest);
+            }
+        }
+        if self.undo.len() > MAX_STEPS {
+            let excess = self.undo.len() - MAX_STEPS;
+            self.undo.drain(..excess);
+        }
+        released
+    }
+
+    /// Ends the drag the last step belongs to, when the slider is let go.
+    /// Edits after it with the same merge key start a new step.
+    pub fn end_merge(&mut self) {
+        self.sealed = true;
+    }
+
+    // a new edit ends the redo branch, and with it the images only it could bring back
+    fn drain_redo(&mut self) -> Vec<SessionImage> {
+        self.redo.drain(..)
+            .filter_map(|command| match command {
+                Command::LoadImage { image, .. } => Some(*image),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The step to revert, now moved to the redo list.
+    pub fn undo(&mut self) -> Option<Command> {
+        let command = self.undo.pop()?;
+        self.redo.push(command.clone());
+        self.sealed = true;
+        Some(command)
+    }
+
+    /// The step to apply again, now moved back to the undo list.
+    pub fn redo(&mut self) -> Option<Command> {
+        let command = self.redo.pop()?;
+        self.undo.push(command.clone());
+        self.sealed = true;
+        Some(command)
+    }
+
+    /// Forgets every step. Returns the undone images, whose pixels the
+    /// renderer can free.
+    pub fn clear(&mut self) -> Vec<SessionImage> {
+        self.undo.clear();
+        self.sealed = true;
+        self.drain_redo()
+    }
+
+    /// Saved palette `index` was deleted. Steps that showed it show the
+    /// built-in palette instead, the ones after it move down an index.
+    pub fn remove_saved_palette(&mut self, index: usize) {
+        for command in self.undo.iter_mut().chain(&mut self.redo) {
+            if let Command::Palette { before, after } = command {
+                before.source = before.source.without_saved(index);
+                after.source = after.source.without_saved(index);
+            }
+        }
+    }
+
+    pub fn next_undo(&self) -> Option<&Command> {
+        self.undo.last()
+    }
+
+    pub fn next_redo(&self) -> Option<&Command> {
+        self.redo.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_management::ColorList;
+    use crate::effects::Adjustment;
+    use crate::histogram::Histogram;
+    use crate::metadata::ImageMetadata;
+
+    fn image(id: usize) -> SessionImage {
+        SessionImage {
+            id,
+            name: format!("{}.png", id),
+            thumbnail: None,
+            metadata: ImageMetadata::default(),
+            effects: EffectStack::default(),
+            adjustments: Adjustments::default(),
+            lut: None,
+            palette: ColorList::new(),
+            histogram: Histogram::from_rgba(&[]),
+        }
+    }
+
+    fn load(id: usize) -> Command {
+        Command::LoadImage { image: Box::new(image(id)), previous: None }
+    }
+
+    // one input event of an exposure slider drag
+    fn exposure(exposure: f32) -> Command {
+        let mut after = Adjustments::default();
+        after.set(Adjustment::Exposure(exposure));
+        let merge = Some(AdjustmentKey::new(&Adjustment::Exposure(exposure), None));
+        Command::Adjustments { before: Box::default(), after: Box::new(after), merge }
+    }
+
+    fn palette(color_index: usize) -> Command {
+        let state = |color_index| PaletteState { source: PaletteSource::Saved(2), color_index, readable: false };
+        Command::Palette { before: state(0), after: state(color_index) }
+    }
+
+    fn undo_len(history: &History) -> usize {
+        let mut history = history.clone();
+        std::iter::from_fn(|| history.undo()).count()
+    }
+
+    #[test]
+    fn merges_a_drag_until_it_ends() {
+        let mut history = History::new();
+        history.record(exposure(0.5));
+        history.record(exposure(1.0));
+        assert_eq!(undo_len(&history), 1);
+        assert_eq!(history.next_undo(), Some(&exposure(1.0)));
+
+        history.end_merge();
+        history.record(exposure(1.5));
+        assert_eq!(undo_len(&history), 2);
+    }
+
+    #[test]
+    fn only_merges_the_same_control() {
+        let mut history = History::new();
+        history.record(exposure(0.5));
+        history.record(Command::FillMode);
+        history.record(exposure(1.0));
+        assert_eq!(undo_len(&history), 3);
+    }
+
+    #[test]
+    fn undo_seals_the_last_step() {
+        let mut history = History::new();
+        history.record(exposure(0.5));
+        history.record(exposure(1.0));
+        history.undo();
+        history.redo();
+        history.record(exposure(1.5));
+        assert_eq!(undo_len(&history), 2);
+    }
+
+    #[test]
+    fn new_edits_drop_the_redo_steps() {
+        let mut history = History::new();
+        history.record(load(0));
+        history.record(Command::FillMode);
+        history.record(load(1));
+        history.undo();
+        history.undo();
+        assert!(history.next_redo().is_some());
+
+        let released = history.record(Command::FillMode);
+        assert_eq!(released.iter().map(|image| image.id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(history.next_redo(), None);
+        assert_eq!(undo_len(&history), 2);
+    }
+
+    #[test]
+    fn keeps_at_most_max_steps() {
+        let mut history = History::new();
+        for _ in 0..MAX_STEPS + 10 {
+            history.record(Command::FillMode);
+        }
+        assert_eq!(undo_len(&history), MAX_STEPS);
+    }
+
+    #[test]
+    fn drops_everything_before_the_oldest_extra_load() {
+        let mut history = History::new();
+        history.record(Command::FillMode);
+        for id in 0..MAX_IMAGE_STEPS {
+            history.record(load(id));
+            history.record(Command::FillMode);
+        }
+        assert_eq!(undo_len(&history), 2 * MAX_IMAGE_STEPS + 1);
+
+        history.record(load(MAX_IMAGE_STEPS));
+        // the first load went, and the fill step before it
+        assert_eq!(undo_len(&history), 2 * MAX_IMAGE_STEPS);
+        let mut oldest = history.clone();
+        let last = std::iter::from_fn(|| oldest.undo()).last();
+        assert_eq!(last, Some(Command::FillMode));
+    }
+
+    #[test]
+    fn follows_deleted_palettes() {
+        let mut history = History::new();
+        history.record(palette(1));
+        history.remove_saved_palette(0);
+        let source = |history: &History| match history.next_undo() {
+            Some(Command::Palette { after, .. }) => after.source,
+            _ => unreachable!(),
+        };
+        assert_eq!(source(&history), PaletteSource::Saved(1));
+        history.remove_saved_palette(1);
+        assert_eq!(source(&history), PaletteSource::BuiltIn);
+    }
+}
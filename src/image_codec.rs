@@ -0,0 +1,155 @@
+use std::io::Cursor;
+
+use image::io::Reader;
+use image::ImageError;
+
+use crate::image_decode::{check_dimensions, DecodeError};
+use crate::metadata::{apply_orientation, ImageMetadata};
+use crate::render_pipeline::WebImage;
+
+impl From<ImageError> for DecodeError {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::Unsupported(_) => DecodeError::UnsupportedFormat,
+            _ => DecodeError::CorruptData,
+        }
+    }
+}
+
+/// Decodes PNG, JPEG, GIF, WebP, BMP or TIFF bytes into straight (not
+/// premultiplied) 8-bit RGBA. The format is sniffed from the data rather
+/// than trusted from the file name or mime type, and the size is checked
+/// before any pixels are decoded. EXIF orientation is applied, so the
+/// result is upright.
+pub fn decode(bytes: &[u8]) -> Result<WebImage, DecodeError> {
+    let reader = || Reader::new(Cursor::new(bytes)).with_guessed_format().map_err(|_| DecodeError::CorruptData);
+    if reader()?.format().is_none() {
+        return Err(DecodeError::UnsupportedFormat);
+    }
+
+    let (width, height) = reader()?.into_dimensions()?;
+    check_dimensions(width, height)?;
+
+    let metadata = ImageMetadata::read(bytes);
+    let img = apply_orientation(reader()?.decode()?.into_rgba8(), metadata.orientation);
+
+    Ok(WebImage {
+        width: img.width(),
+        height: img.height(),
+        data: img.into_raw(),
+        metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_pipeline::max_texture_dimension;
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+    // 1x1 lossless and lossy webp, the image crate can't encode webp itself
+    const WEBP_LOSSLESS: &[u8] = &[
+        0x52, 0x49, 0x46, 0x46, 0x1a, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50,
+        0x56, 0x50, 0x38, 0x4c, 0x0d, 0x00, 0x00, 0x00, 0x2f, 0x00, 0x00, 0x00,
+        0x10, 0x07, 0x10, 0x11, 0x11, 0x88, 0x88, 0xfe, 0x07, 0x00,
+    ];
+    const WEBP_LOSSY: &[u8] = &[
+        0x52, 0x49, 0x46, 0x46, 0x22, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50,
+        0x56, 0x50, 0x38, 0x20, 0x16, 0x00, 0x00, 0x00, 0x30, 0x01, 0x00, 0x9d,
+        0x01, 0x2a, 0x01, 0x00, 0x01, 0x00, 0x0e, 0xc0, 0xfe, 0x25, 0xa4, 0x00,
+        0x03, 0x70, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 100, y as u8 * 200, 50, 255]))
+    }
+
+    fn encode(format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(sample()).write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn lossless_formats_round_trip() {
+        for format in [ImageOutputFormat::Png, ImageOutputFormat::Bmp, ImageOutputFormat::Tiff] {
+            let img = decode(&encode(format.clone())).unwrap();
+            assert_eq!((img.width, img.height), (3, 2), "{:?}", format);
+            assert_eq!(img.data, sample().into_raw(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_jpeg_and_gif() {
+        for format in [ImageOutputFormat::Jpeg(90), ImageOutputFormat::Gif] {
+            let img = decode(&encode(format.clone())).unwrap();
+            assert_eq!((img.width, img.height), (3, 2), "{:?}", format);
+            assert_eq!(img.data.len(), 3 * 2 * 4, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn decodes_webp() {
+        for bytes in [WEBP_LOSSLESS, WEBP_LOSSY] {
+            let img = decode(bytes).unwrap();
+            assert_eq!((img.width, img.height), (1, 1));
+            assert_eq!(img.data.len(), 4);
+        }
+    }
+
+    // a little endian TIFF block holding only the orientation tag
+    fn orientation_exif(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(0x0112u16.to_le_bytes());
+        tiff.extend(3u16.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(orientation.to_le_bytes());
+        tiff.extend([0, 0, 0, 0, 0, 0]);
+        tiff
+    }
+
+    #[test]
+    fn rotates_by_exif_orientation() {
+        // red on the left, blue on the right
+        let img = RgbaImage::from_fn(16, 8, |x, _| if x < 8 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        let mut jpeg = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(img).write_to(&mut jpeg, ImageOutputFormat::Jpeg(95)).unwrap();
+        let jpeg = jpeg.into_inner();
+
+        let exif = orientation_exif(6);
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xff, 0xe1]);
+        bytes.extend(((2 + 6 + exif.len()) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(&exif);
+        bytes.extend(&jpeg[2..]);
+
+        // 6 is rotated a quarter turn clockwise, the left edge ends up on top
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.metadata.orientation, 6);
+        assert_eq!((decoded.width, decoded.height), (8, 16));
+        let pixel = |x: u32, y: u32| &decoded.data[((y * decoded.width + x) * 4) as usize..][..3];
+        assert!(pixel(4, 1)[0] > 200 && pixel(4, 1)[2] < 60, "{:?}", pixel(4, 1));
+        assert!(pixel(4, 14)[2] > 200 && pixel(4, 14)[0] < 60, "{:?}", pixel(4, 14));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(decode(b"definitely not an image").err(), Some(DecodeError::UnsupportedFormat));
+        assert_eq!(decode(&encode(ImageOutputFormat::Png)[..40]).err(), Some(DecodeError::CorruptData));
+    }
+
+    #[test]
+    fn rejects_oversized_images_before_decoding() {
+        let mut bytes = Cursor::new(Vec::new());
+        let wide = RgbaImage::new(max_texture_dimension() + 1, 1);
+        image::DynamicImage::ImageRgba8(wide).write_to(&mut bytes, ImageOutputFormat::Png).unwrap();
+
+        match decode(bytes.get_ref()) {
+            Err(DecodeError::TooLarge { width, .. }) => assert_eq!(width, max_texture_dimension() + 1),
+            other => panic!("expected TooLarge, got {:?}", other.map(|img| img.width)),
+        }
+    }
+}
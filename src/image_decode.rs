@@ -1,106 +1,293 @@
 use js_sys::Uint8Array;
-use wasm_bindgen::JsCast;
-use web_sys::File;
-
-pub fn get_file(id: &str) -> Option<File> {
-    log::info!("getting file");
-    let input_el = match web_sys::window()
-        .unwrap().document()
-        .unwrap().get_element_by_id(id)
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, DataTransfer, DragEvent, Event, File, FileList, KeyboardEvent};
+
+/// Every file picked in the file input `id`, in the order the browser lists them.
+pub fn get_files(id: &str) -> Vec<File> {
+    log::info!("getting files");
+    let input = match get_optional_element_by_id(id)
+        .and_then(|el| el.dyn_into::<web_sys::HtmlInputElement>().ok())
         {
-            Some(element) => element,
-            None => return None
+            Some(input) => input,
+            None => return Vec::new()
         };
-    let input = input_el.dyn_into::<web_sys::HtmlInputElement>()
-    .map_err(|_| ())
-    .expect("Should have found input field");
-
+    input.files().map(file_list).unwrap_or_default()
+}
 
-    let items = match input.files() {
-        Some(file) => file,
-        None => return None
-    };
-    let file = match items.get(0) {
-        Some(n) => return Some(n),
-        None => return None
-    };
-     
+fn file_list(files: FileList) -> Vec<File> {
+    (0..files.length()).filter_map(|i| files.get(i)).collect()
 }
 
 
 use web_sys::Url;
 use web_sys::ImageData;
 use web_sys::CanvasRenderingContext2d;
-use web_sys::ImageBitmap;
-use web_sys::HtmlImageElement;
+use web_sys::{ImageBitmap, ImageBitmapOptions, ImageOrientation};
 
-use crate::render_pipeline::WebImage;
+use crate::render_pipeline::{max_texture_dimension, WebImage};
+use crate::image_codec;
+use crate::metadata::ImageMetadata;
 use wasm_logger;
+use wasm_bindgen_futures::JsFuture;
 
+/// Which path turns a picked `File` into pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Decoder {
+    /// Browser decode through the hidden `decode-canvas`. Limited to the
+    /// formats the browser knows, and the 2d context may premultiply and
+    /// color convert.
+    Canvas,
+    /// Pure-Rust decode of the file bytes, see `image_codec`.
+    Rust,
+}
 
-pub async fn canvas_decode(file: File) -> Option<WebImage> {
-    let url = Url::create_object_url_with_blob(file.as_ref()).unwrap();
-    let canvas = get_element_by_id("decode-canvas").dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|_| ())
-        .unwrap();    
-    
-    let img_el = HtmlImageElement::new().unwrap();
-    img_el.set_src(url.as_str());
+impl Decoder {
+    pub fn toggle(&self) -> Decoder {
+        match self {
+            Decoder::Canvas => Decoder::Rust,
+            Decoder::Rust => Decoder::Canvas,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Decoder::Canvas => "Canvas",
+            Decoder::Rust => "Rust",
+        }
+    }
+}
 
-    async {
-        while !img_el.complete() {
-            wasm_timer::Delay::new(core::time::Duration::from_millis(1)).await;
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// Not an image, or a format the chosen decoder can't read.
+    UnsupportedFormat,
+    /// Recognised format, but the data couldn't be decoded.
+    CorruptData,
+    /// Larger than the renderer's maximum texture size.
+    TooLarge { width: u32, height: u32, max: u32 },
+    /// The hidden `decode-canvas` or its 2d context is missing.
+    CanvasUnavailable,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnsupportedFormat => write!(f, "Unsupported image format"),
+            DecodeError::CorruptData => write!(f, "Image data is corrupt"),
+            DecodeError::TooLarge { width, height, max } => {
+                write!(f, "Image is {}x{}, the limit is {}px per side", width, height, max)
+            }
+            DecodeError::CanvasUnavailable => write!(f, "Decode canvas unavailable"),
         }
-    }.await;
+    }
+}
 
-    let img_width = img_el.natural_width();
-    let img_height = img_el.natural_height();
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), DecodeError> {
+    let max = max_texture_dimension();
+    if width > max || height > max {
+        return Err(DecodeError::TooLarge { width, height, max });
+    }
+    Ok(())
+}
+
+pub async fn decode_file(file: File, decoder: Decoder) -> Result<WebImage, DecodeError> {
+    match decoder {
+        Decoder::Canvas => canvas_decode(file).await,
+        Decoder::Rust => rust_decode(file).await,
+    }
+}
+
+pub async fn read_file(file: &File) -> Result<Vec<u8>, DecodeError> {
+    let buffer = JsFuture::from(file.array_buffer()).await
+        .map_err(|_| DecodeError::CorruptData)?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+pub async fn rust_decode(file: File) -> Result<WebImage, DecodeError> {
+    let bytes = read_file(&file).await?;
+    image_codec::decode(&bytes)
+}
+
+pub async fn canvas_decode(file: File) -> Result<WebImage, DecodeError> {
+    // the browser doesn't say why a load failed, so go by what the file claims to be
+    if !file.type_().starts_with("image/") {
+        return Err(DecodeError::UnsupportedFormat);
+    }
+
+    let canvas = get_optional_element_by_id("decode-canvas")
+        .and_then(|el| el.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+        .ok_or(DecodeError::CanvasUnavailable)?;
+    let canvas_context = canvas.get_context("2d")
+        .ok()
+        .flatten()
+        .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+        .ok_or(DecodeError::CanvasUnavailable)?;
+
+    let metadata = ImageMetadata::read(&read_file(&file).await?);
+
+    // the bitmap is decoded upright from the EXIF orientation, as the spec
+    // requires for "from-image", so it isn't rotated again here
+    let mut options = ImageBitmapOptions::new();
+    options.image_orientation(ImageOrientation::FromImage);
+    let window = web_sys::window().ok_or(DecodeError::CanvasUnavailable)?;
+    let decoding = window.create_image_bitmap_with_blob_and_image_bitmap_options(file.as_ref(), &options)
+        .map_err(|_| DecodeError::CanvasUnavailable)?;
+    let bitmap: ImageBitmap = JsFuture::from(decoding).await
+        .map_err(|_| DecodeError::CorruptData)?
+        .unchecked_into();
+
+    let img_width = bitmap.width();
+    let img_height = bitmap.height();
+    if img_width == 0 || img_height == 0 {
+        return Err(DecodeError::CorruptData);
+    }
+    check_dimensions(img_width, img_height)?;
     
     canvas.set_height(img_height);
     canvas.set_width(img_width);
     
-    if let Some(canvas_context) = canvas.get_context("2d").unwrap() {
-        let canvas_context = canvas_context.dyn_into::<web_sys::CanvasRenderingContext2d>().unwrap();
-        canvas_context.draw_image_with_html_image_element(&img_el, 0.0, 0.0).expect("failed to draw image");
-        let data = canvas_context.get_image_data(0.0, 0.0, img_width as f64, img_height as f64)
-            .expect("failed to get image data")
-            .data()
-            .to_vec();
-
-        return Some(WebImage {
-            width: img_width,
-            height: img_height,
-            data: data
-        });
-    };
+    let drawn = canvas_context.draw_image_with_image_bitmap(&bitmap, 0.0, 0.0);
+    bitmap.close();
+    drawn.map_err(|_| DecodeError::CorruptData)?;
+    let data = canvas_context.get_image_data(0.0, 0.0, img_width as f64, img_height as f64)
+        .map_err(|_| DecodeError::CanvasUnavailable)?
+        .data()
+        .to_vec();
+
+    Ok(WebImage {
+        width: img_width,
+        height: img_height,
+        data,
+        metadata,
+    })
+}
 
-    None
+/// Calls `on_files` with the files dropped on `target` or pasted anywhere
+/// in the document. The listeners live as long as the page.
+pub fn listen_for_files(target: &Element, on_files: impl Fn(Vec<File>) + 'static) {
+    let on_files = std::rc::Rc::new(on_files);
+
+    // the browser only allows a drop where dragover was cancelled
+    let dragover = Closure::<dyn Fn(DragEvent)>::new(|evt: DragEvent| evt.prevent_default());
+
+    let drop = Closure::<dyn Fn(DragEvent)>::new({
+        let on_files = on_files.clone();
+        move |evt: DragEvent| {
+            // otherwise the browser navigates to the dropped file
+            evt.prevent_default();
+            let files = evt.data_transfer().and_then(|data| data.files()).map(file_list).unwrap_or_default();
+            if !files.is_empty() {
+                on_files(files);
+            }
+        }
+    });
+
+    // ClipboardEvent is behind web-sys's unstable APIs, so reach for clipboardData directly
+    let paste = Closure::<dyn Fn(Event)>::new(move |evt: Event| {
+        let data = js_sys::Reflect::get(&evt, &JsValue::from_str("clipboardData")).ok()
+            .and_then(|data| data.dyn_into::<DataTransfer>().ok());
+        let files = data.and_then(|data| data.files()).map(file_list).unwrap_or_default();
+        // plain text pastes are left alone for the inputs
+        if !files.is_empty() {
+            evt.prevent_default();
+            on_files(files);
+        }
+    });
+
+    let _ = target.add_event_listener_with_callback("dragover", dragover.as_ref().unchecked_ref());
+    let _ = target.add_event_listener_with_callback("drop", drop.as_ref().unchecked_ref());
+    if let Some(document) = web_sys::window().and_then(|win| win.document()) {
+        let _ = document.add_event_listener_with_callback("paste", paste.as_ref().unchecked_ref());
+    }
+
+    dragover.forget();
+    drop.forget();
+    paste.forget();
+}
+
+/// Calls `on_undo` for Ctrl+Z and `on_redo` for Ctrl+Shift+Z or Ctrl+Y,
+/// Cmd on macOS. Text fields keep their own undo.
+pub fn listen_for_undo(on_undo: impl Fn() + 'static, on_redo: impl Fn() + 'static) {
+    let keydown = Closure::<dyn Fn(KeyboardEvent)>::new(move |evt: KeyboardEvent| {
+        if !(evt.ctrl_key() || evt.meta_key()) {
+            return;
+        }
+        let in_text_field = evt.target()
+            .and_then(|target| target.dyn_into::<web_sys::HtmlInputElement>().ok())
+            .is_some_and(|input| input.type_() != "range");
+        if in_text_field {
+            return;
+        }
+
+        match (evt.key().to_lowercase().as_str(), evt.shift_key()) {
+            ("z", false) => on_undo(),
+            ("z", true) | ("y", false) => on_redo(),
+            _ => return,
+        }
+        evt.prevent_default();
+    });
+
+    if let Some(document) = web_sys::window().and_then(|win| win.document()) {
+        let _ = document.add_event_listener_with_callback("keydown", keydown.as_ref().unchecked_ref());
+    }
+    keydown.forget();
 }
 
 use web_sys::Element;
 pub fn get_element_by_id(id: &str) -> Element {
+    get_optional_element_by_id(id).unwrap()
+}
+
+pub fn get_optional_element_by_id(id: &str) -> Option<Element> {
     web_sys::window()
     .and_then(|win| win.document())
     .and_then(|doc| {
         doc.get_element_by_id(id)
     })
-    .unwrap()
 }
 
 
-pub fn save_canvas() {
-    let canvas = get_element_by_id("canvas").dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|_| ())
-        .unwrap();
-    
-    let image = canvas.to_data_url().unwrap();
+/// Wraps `bytes` in a Blob and returns an object URL for it.
+pub fn object_url(bytes: &[u8], mime: &str) -> Option<String> {
+    let parts = js_sys::Array::of1(&Uint8Array::from(bytes));
+    let mut options = BlobPropertyBag::new();
+    options.type_(mime);
+    let blob = match Blob::new_with_u8_array_sequence_and_options(&parts, &options) {
+        Ok(blob) => blob,
+        Err(e) => {
+            log::error!("couldn't create blob: {:?}", e);
+            return None;
+        }
+    };
+    Url::create_object_url_with_blob(&blob).ok()
+}
+
+pub fn revoke_object_url(url: &str) {
+    let _ = Url::revoke_object_url(url);
+}
+
+/// Offers `bytes` as a file download through the hidden `download-anchor`.
+pub fn download_bytes(bytes: &[u8], mime: &str, filename: &str) {
+    let url = match object_url(bytes, mime) {
+        Some(url) => url,
+        None => return,
+    };
 
     let anchor = get_element_by_id("download-anchor").dyn_into::<web_sys::HtmlAnchorElement>()
         .map_err(|_| ())
         .unwrap();
     
-    anchor.set_attribute("download", "render.png");
-    anchor.set_href(image.as_str());
+    anchor.set_download(filename);
+    anchor.set_href(url.as_str());
     anchor.click();
-    
-}
\ No newline at end of file
+
+    if let Some(previous) = DOWNLOAD_URL.with(|last| last.replace(Some(url))) {
+        let _ = Url::revoke_object_url(&previous);
+    }
+}
+
+thread_local! {
+    // revoked on the next download, revoking straight after `click` can cancel it
+    static DOWNLOAD_URL: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
@@ -0,0 +1,179 @@
+/// Pixels within which a dragged panel's edge snaps to the viewport or to
+/// another panel's edge.
+pub const SNAP_DISTANCE: f64 = 12.0;
+
+/// Grid sizes the layout panel cycles through, 0 turns the grid off.
+pub const GRID_SIZES: [u32; 4] = [0, 8, 16, 32];
+
+/// A panel dropped with the pointer this close to the right edge of the
+/// viewport goes into the dock rail.
+pub const DOCK_ZONE: f64 = 24.0;
+
+/// Screen space box of a panel, in CSS pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn new((x, y): (f64, f64), (width, height): (f64, f64)) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn right(&self) -> f64 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> f64 {
+        self.y + self.height
+    }
+
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.right() && other.x < self.right()
+            && self.y < other.bottom() && other.y < self.bottom()
+    }
+
+    fn at(&self, (x, y): (f64, f64)) -> Rect {
+        Rect { x, y, ..*self }
+    }
+}
+
+/// Where a panel being dragged to `rect` should sit. Each axis snaps to the
+/// nearest viewport or panel edge within `SNAP_DISTANCE`, falling back to
+/// the grid, and the result is kept inside the viewport.
+pub fn snap(rect: Rect, others: &[Rect], view: (f64, f64), grid: u32) -> (f64, f64) {
+    // only panels level with this one have edges worth lining up with
+    let beside = |o: &&Rect| rect.y < o.bottom() + SNAP_DISTANCE && o.y < rect.bottom() + SNAP_DISTANCE;
+    let above = |o: &&Rect| rect.x < o.right() + SNAP_DISTANCE && o.x < rect.right() + SNAP_DISTANCE;
+
+    let x_edges: Vec<f64> = others.iter().filter(beside).flat_map(|o| [o.x, o.right()]).collect();
+    let y_edges: Vec<f64> = others.iter().filter(above).flat_map(|o| [o.y, o.bottom()]).collect();
+
+    let x = snap_axis(rect.x, rect.width, view.0, &x_edges, grid);
+    let y = snap_axis(rect.y, rect.height, view.1, &y_edges, grid);
+    clamp(rect.at((x, y)), view)
+}
+
+// start and end of the panel are both tried against every edge, the closest wins
+fn snap_axis(start: f64, length: f64, view: f64, edges: &[f64], grid: u32) -> f64 {
+    let nearest = [0.0, view].iter()
+        .chain(edges)
+        .flat_map(|edge| [edge - start, edge - (start + length)])
+        .filter(|delta| delta.abs() <= SNAP_DISTANCE)
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()));
+
+    match nearest {
+        Some(delta) => start + delta,
+        None if grid > 0 => (start / grid as f64).round() * grid as f64,
+        None => start,
+    }
+}
+
+/// Whether a panel dropped with the pointer at `x` goes into the dock rail.
+pub fn in_dock_zone(x: f64, view_width: f64) -> bool {
+    x >= view_width - DOCK_ZONE
+}
+
+/// Moves `rect` back inside the viewport. A panel larger than the viewport
+/// keeps its top left corner on screen.
+pub fn clamp(rect: Rect, (width, height): (f64, f64)) -> (f64, f64) {
+    (
+        rect.x.min(width - rect.width).max(0.0),
+        rect.y.min(height - rect.height).max(0.0),
+    )
+}
+
+/// Where a panel dropped at `rect` should go so it doesn't cover `others`:
+/// the closest spot beside one of the panels it overlaps that is free and on
+/// screen. Stays put when there's no overlap or nowhere better.
+pub fn avoid_overlap(rect: Rect, others: &[Rect], view: (f64, f64)) -> (f64, f64) {
+    let is_free = |candidate: &Rect| !others.iter().any(|o| candidate.overlaps(o));
+    if is_free(&rect) {
+        return (rect.x, rect.y);
+    }
+
+    let distance = |(x, y): (f64, f64)| (x - rect.x).powi(2) + (y - rect.y).powi(2);
+    others.iter()
+        .filter(|o| rect.overlaps(o))
+        .flat_map(|o| [
+            (o.x - rect.width, rect.y),
+            (o.right(), rect.y),
+            (rect.x, o.y - rect.height),
+            (rect.x, o.bottom()),
+        ])
+        .map(|pos| clamp(rect.at(pos), view))
+        .filter(|&pos| is_free(&rect.at(pos)))
+        .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or((rect.x, rect.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: (f64, f64) = (1000.0, 800.0);
+
+    fn panel(x: f64, y: f64) -> Rect {
+        Rect::new((x, y), (200.0, 100.0))
+    }
+
+    #[test]
+    fn snaps_to_the_viewport_edge_within_reach() {
+        let near = SNAP_DISTANCE - 2.0;
+        assert_eq!(snap(panel(near, 300.0), &[], VIEW, 0), (0.0, 300.0));
+        assert_eq!(snap(panel(VIEW.0 - 200.0 - near, 300.0), &[], VIEW, 0), (VIEW.0 - 200.0, 300.0));
+
+        let far = SNAP_DISTANCE + 2.0;
+        assert_eq!(snap(panel(far, 300.0), &[], VIEW, 0), (far, 300.0));
+    }
+
+    #[test]
+    fn snaps_to_panels_level_with_it() {
+        let other = panel(100.0, 300.0);
+        // the left edge meets the other's right edge
+        assert_eq!(snap(panel(305.0, 330.0), &[other], VIEW, 0), (300.0, 330.0));
+        // and the tops line up too
+        assert_eq!(snap(panel(305.0, 310.0), &[other], VIEW, 0), (300.0, 300.0));
+        // too far below to line up with
+        assert_eq!(snap(panel(305.0, 600.0), &[other], VIEW, 0), (305.0, 600.0));
+    }
+
+    #[test]
+    fn falls_back_to_the_grid() {
+        assert_eq!(snap(panel(123.0, 245.0), &[], VIEW, 16), (128.0, 240.0));
+    }
+
+    #[test]
+    fn stays_on_screen() {
+        assert_eq!(clamp(panel(-50.0, 750.0), VIEW), (0.0, 700.0));
+        // wider than the view, the corner stays visible
+        assert_eq!(clamp(Rect::new((300.0, 0.0), (1200.0, 100.0)), VIEW), (0.0, 0.0));
+    }
+
+    #[test]
+    fn docks_near_the_right_edge() {
+        assert!(in_dock_zone(VIEW.0 - DOCK_ZONE / 2.0, VIEW.0));
+        assert!(!in_dock_zone(VIEW.0 - DOCK_ZONE * 2.0, VIEW.0));
+    }
+
+    #[test]
+    fn moves_off_an_overlapped_panel() {
+        let other = panel(400.0, 300.0);
+        assert_eq!(avoid_overlap(panel(100.0, 100.0), &[other], VIEW), (100.0, 100.0));
+
+        // mostly below the other one, so it moves down rather than sideways
+        assert_eq!(avoid_overlap(panel(420.0, 380.0), &[other], VIEW), (420.0, 400.0));
+        // mostly to the right
+        assert_eq!(avoid_overlap(panel(580.0, 310.0), &[other], VIEW), (600.0, 310.0));
+    }
+
+    #[test]
+    fn stays_put_with_nowhere_to_go() {
+        let view = (200.0, 100.0);
+        let other = panel(0.0, 0.0);
+        assert_eq!(avoid_overlap(panel(0.0, 0.0), &[other], view), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::color_space::{linear_to_srgb, srgb_to_linear};
+use crate::effects::Adjustments;
+use crate::render_pipeline::max_texture_dimension;
+
+pub const LUT_MIME: &str = "text/plain";
+/// Entries per axis of an exported `.cube`, the size Resolve writes.
+pub const EXPORT_SIZE: usize = 33;
+
+const MAX_1D_SIZE: usize = 65536;
+const MAX_3D_SIZE: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LutKind {
+    /// A curve per channel.
+    OneD,
+    /// A lattice of `size`³ colors, red changing fastest.
+    ThreeD,
+}
+
+/// A color lookup table read from an Adobe/Resolve `.cube` file. Works on
+/// display encoded values, the renderer applies it to sRGB.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    pub title: String,
+    pub kind: LutKind,
+    /// Entries per axis.
+    pub size: usize,
+    /// Input values mapped to the first and last entries.
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    table: Vec<[f32; 3]>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LutError {
+    Invalid(String),
+    /// Packs into a texture wider or taller than WebGL2 allows.
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for LutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LutError::Invalid(reason) => write!(f, "Not a valid .cube file: {}", reason),
+            LutError::TooLarge(size) => write!(f, "A LUT of size {} is too large to use", size),
+        }
+    }
+}
+
+/// Where a LUT's entries go in its 2D texture. 3D LUTs lay their blue
+/// slices out in a grid of `columns`, 1D LUTs wrap into rows `width` long.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Packing {
+    pub width: u32,
+    pub height: u32,
+    /// Slices per row for 3D, entries per row for 1D.
+    pub columns: u32,
+}
+
+impl Lut {
+    /// Parses `.cube` text. Sizes that can't be packed into a texture under
+    /// the WebGL2 limits are refused here, before anything is uploaded.
+    pub fn parse(text: &str, name: &str) -> Result<Lut, LutError> {
+        let invalid = LutError::Invalid;
+        let mut title = None;
+        let mut kind = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let triplet = |values: &str| -> Result<[f32; 3], LutError> {
+                let values: Vec<f32> = values.split_whitespace()
+                    .map(|v| v.parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid(format!("line {}: expected numbers", number)))?;
+                <[f32; 3]>::try_from(values).map_err(|_| invalid(format!("line {}: expected three values", number)))
+            };
+            let size = || rest.parse::<usize>().map_err(|_| invalid(format!("line {}: bad size", number)));
+
+            match keyword {
+                "TITLE" => title = Some(rest.trim_matches('"').to_string()),
+                "LUT_1D_SIZE" => kind = Some((LutKind::OneD, size()?)),
+                "LUT_3D_SIZE" => kind = Some((LutKind::ThreeD, size()?)),
+                "DOMAIN_MIN" => domain_min = triplet(rest)?,
+                "DOMAIN_MAX" => domain_max = triplet(rest)?,
+                // Resolve's shorthand for a domain that's the same on every channel
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => {
+                    let range: Vec<f32> = rest.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                    if let [min, max] = range[..] {
+                        domain_min = [min; 3];
+                        domain_max = [max; 3];
+                    }
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) => {
+                    table.push(triplet(line)?);
+                }
+                // other keywords from newer versions of the format don't change the table
+                _ => {}
+            }
+        }
+
+        let (kind, size) = kind.ok_or_else(|| invalid(String::from("missing LUT_1D_SIZE or LUT_3D_SIZE")))?;
+        let max_size = match kind {
+            LutKind::OneD => MAX_1D_SIZE,
+            LutKind::ThreeD => MAX_3D_SIZE,
+        };
+        // before cubing it, a made up size can overflow a 32 bit usize
+        if size < 2 || size > max_size {
+            return Err(invalid(format!("size {} is out of range", size)));
+        }
+        let entries = match kind {
+            LutKind::OneD => size,
+            LutKind::ThreeD => size.pow(3),
+        };
+        if table.len() != entries {
+            return Err(invalid(format!("expected {} entries, found {}", entries, table.len())));
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err(invalid(String::from("empty domain")));
+        }
+
+        let title = title.filter(|t| !t.is_empty())
+            .unwrap_or_else(|| name.rsplit_once('.').map_or(name, |(stem, _)| stem).to_string());
+        let lut = Lut { title, kind, size, domain_min, domain_max, table };
+        lut.packing(max_texture_dimension())?;
+        Ok(lut)
+    }
+
+    /// A 3D LUT of `size` entries per axis over 0-1, each entry `grade`
+    /// of its coordinates.
+    pub fn bake(title: &str, size: usize, grade: impl Fn([f32; 3]) -> [f32; 3]) -> Lut {
+        let step = (size - 1) as f32;
+        let table = (0..size.pow(3))
+            .map(|i| grade([i % size, i / size % size, i / (size * size)].map(|c| c as f32 / step)))
+            .collect();
+        Lut {
+            title: title.to_string(),
+            kind: LutKind::ThreeD,
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = format!("TITLE \"{}\"\n", self.title.replace('"', "'"));
+        match self.kind {
+            LutKind::OneD => text.push_str(&format!("LUT_1D_SIZE {}\n", self.size)),
+            LutKind::ThreeD => text.push_str(&format!("LUT_3D_SIZE {}\n", self.size)),
+        }
+        if self.domain_min != [0.0; 3] || self.domain_max != [1.0; 3] {
+            let [r, g, b] = self.domain_min;
+            text.push_str(&format!("DOMAIN_MIN {} {} {}\n", r, g, b));
+            let [r, g, b] = self.domain_max;
+            text.push_str(&format!("DOMAIN_MAX {} {} {}\n", r, g, b));
+        }
+        for [r, g, b] in &self.table {
+            text.push_str(&format!("{:.6} {:.6} {:.6}\n", r, g, b));
+        }
+        text
+    }
+
+    /// The adjust pass as a 3D LUT: `adjustments` with their curves, then
+    /// `lut` blended in by their LUT intensity. Inputs and outputs are sRGB
+    /// encoded.
+    pub fn from_adjustments(adjustments: &Adjustments, lut: Option<&Lut>) -> Lut {
+        Lut::bake("Adjustments", EXPORT_SIZE, |rgb| {
+            let adjusted = adjustments.apply(rgb.map(|c| srgb_to_linear(c as f64) as f32));
+            let encoded = adjustments.curves.apply(adjusted.map(|c| linear_to_srgb(c as f64) as f32));
+            match lut {
+                Some(lut) => {
+                    let graded = lut.apply(encoded);
+                    [0, 1, 2].map(|i| {
+                        let graded = graded[i].clamp(0.0, 1.0);
+                        encoded[i] + (graded - encoded[i]) * adjustments.lut_intensity
+                    })
+                }
+                None => encoded,
+            }
+        })
+    }
+
+    /// `Film look` becomes `Film look.cube`.
+    pub fn file_name(&self) -> String {
+        let stem = if self.title.trim().is_empty() { "lut" } else { self.title.trim() };
+        format!("{}.cube", stem.replace(['/', '\\'], "-"))
+    }
+
+    /// How the table fits in a texture no larger than `max` on either side.
+    pub fn packing(&self, max: u32) -> Result<Packing, LutError> {
+        let size = self.size as u32;
+        let packing = match self.kind {
+            LutKind::OneD => {
+                let columns = size.min(max);
+                Packing { width: columns, height: size.div_ceil(columns), columns }
+            }
+            LutKind::ThreeD => {
+                let columns = (max / size).clamp(1, size);
+                Packing { width: columns * size, height: size.div_ceil(columns) * size, columns }
+            }
+        };
+        if packing.width > max || packing.height > max {
+            return Err(LutError::TooLarge(self.size));
+        }
+        Ok(packing)
+    }
+
+    /// RGBA32F texels laid out as `packing` says, alpha unused.
+    pub fn texels(&self, packing: Packing) -> Vec<f32> {
+        let mut texels = vec![0.0; (packing.width * packing.height * 4) as usize];
+        let size = self.size;
+        let columns = packing.columns as usize;
+        for (i, rgb) in self.table.iter().enumerate() {
+            let (x, y) = match self.kind {
+                LutKind::OneD => (i % columns, i / columns),
+                LutKind::ThreeD => {
+                    let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                    (b % columns * size + r, b / columns * size + g)
+                }
+            };
+            let at = (y * packing.width as usize + x) * 4;
+            texels[at..at + 3].copy_from_slice(rgb);
+            texels[at + 3] = 1.0;
+        }
+        texels
+    }
+
+    /// Looks up `rgb` with linear interpolation, the way the shader does.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = (self.size - 1) as f32;
+        let position = [0, 1, 2].map(|i| {
+            let t = (rgb[i] - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i]);
+            t.clamp(0.0, 1.0) * scale
+        });
+        let lower = position.map(|p| (p.floor() as usize).min(self.size - 2));
+        let fraction = [0, 1, 2].map(|i| position[i] - lower[i] as f32);
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+
+        match self.kind {
+            LutKind::OneD => [0, 1, 2].map(|c| {
+                let (a, b) = (self.table[lower[c]][c], self.table[lower[c] + 1][c]);
+                a + (b - a) * fraction[c]
+            }),
+            LutKind::ThreeD => {
+                let entry = |r: usize, g: usize, b: usize| self.table[(b * self.size + g) * self.size + r];
+                let [r, g, b] = lower;
+                let plane = |b: usize| lerp(
+                    lerp(entry(r, g, b), entry(r + 1, g, b), fraction[0]),
+                    lerp(entry(r, g + 1, b), entry(r + 1, g + 1, b), fraction[0]),
+                    fraction[1],
+                );
+                lerp(plane(b), plane(b + 1), fraction[2])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_3d_lut() {
+        let text = "TITLE \"Invert\"\nLUT_3D_SIZE 2\n1 1 1\n0 1 1\n1 0 1\n0 0 1\n1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+        let lut = Lut::parse(text, "invert.cube").unwrap();
+        assert_eq!(lut.title, "Invert");
+        assert_eq!((lut.kind, lut.size), (LutKind::ThreeD, 2));
+        assert_eq!(lut.apply([0.0, 0.0, 1.0]), [1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn refuses_sizes_past_the_limit() {
+        let too_large = Lut::parse("LUT_3D_SIZE 2000\n0 0 0\n", "large.cube");
+        assert_eq!(too_large, Err(LutError::Invalid(String::from("size 2000 is out of range"))));
+
+        // cubing this would overflow even a 64 bit usize
+        let overflowing = format!("LUT_3D_SIZE {}\n0 0 0\n", usize::MAX);
+        assert!(matches!(Lut::parse(&overflowing, "overflow.cube"), Err(LutError::Invalid(_))));
+
+        let one_d = format!("LUT_1D_SIZE {}\n0 0 0\n", MAX_1D_SIZE + 1);
+        assert!(matches!(Lut::parse(&one_d, "long.cube"), Err(LutError::Invalid(_))));
+    }
+
+    #[test]
+    fn packs_3d_slices_in_a_grid() {
+        let lut = Lut::bake("Identity", 4, |rgb| rgb);
+        let packing = lut.packing(8).unwrap();
+        assert_eq!(packing, Packing { width: 8, height: 8, columns: 2 });
+
+        let texels = lut.texels(packing);
+        assert_eq!(texels.len(), 8 * 8 * 4);
+        // red 1, green 2 in the blue 3 slice, the second row's second column
+        let at = (6 * 8 + 5) * 4;
+        assert_eq!(texels[at..at + 4], [1.0 / 3.0, 2.0 / 3.0, 1.0, 1.0]);
+
+        assert_eq!(lut.packing(3), Err(LutError::TooLarge(4)));
+    }
+
+    #[test]
+    fn wraps_1d_entries_into_rows() {
+        let lut = Lut {
+            title: String::from("Ramp"),
+            kind: LutKind::OneD,
+            size: 5,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table: (0..5).map(|i| [i as f32; 3]).collect(),
+        };
+        let packing = lut.packing(4).unwrap();
+        assert_eq!(packing, Packing { width: 4, height: 2, columns: 4 });
+
+        let texels = lut.texels(packing);
+        assert_eq!(texels[3 * 4..4 * 4], [3.0, 3.0, 3.0, 1.0]);
+        assert_eq!(texels[4 * 4..5 * 4], [4.0, 4.0, 4.0, 1.0]);
+        // the rest of the last row is left empty
+        assert_eq!(texels[5 * 4..], [0.0; 12]);
+
+        assert_eq!(lut.packing(2), Err(LutError::TooLarge(5)));
+    }
+
+    #[test]
+    fn encodes_what_it_parses() {
+        let mut lut = Lut::bake("Warm \"film\"", 3, |[r, g, b]| [r.powf(0.8), g, b * 0.9 + 0.05]);
+        lut.domain_max = [1.0, 1.0, 2.0];
+        let parsed = Lut::parse(&lut.encode(), "warm.cube").unwrap();
+
+        assert_eq!(parsed.title, "Warm 'film'");
+        assert_eq!((parsed.kind, parsed.size), (lut.kind, lut.size));
+        assert_eq!((parsed.domain_min, parsed.domain_max), (lut.domain_min, lut.domain_max));
+        // entries are written to six places
+        for (a, b) in parsed.table.iter().zip(&lut.table) {
+            for i in 0..3 {
+                assert!((a[i] - b[i]).abs() < 1e-6, "{:?} and {:?} differ", a, b);
+            }
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Pointer;
 use std::hash::Hash;
+use std::rc::Rc;
 
 use dioxus::prelude::*;
 use dioxus::events::{MouseEvent, FormEvent, PointerData};
 use dioxus::core::UiEvent;
+use dioxus::fermi::{use_atom_root, AtomRoot, Readable};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::File;
 //use img_render::run;
 //use img_render::WebImage;
 //use img_render::FrontendEvent;
@@ -15,14 +19,58 @@ use winit::event_loop::{EventLoopProxy};
 use wasm_logger;
 
 mod image_decode;
-use image_decode::{get_file, canvas_decode, save_canvas};
+use image_decode::{get_files, decode_file, get_optional_element_by_id, listen_for_files, listen_for_undo, read_file, download_bytes, Decoder};
 
+mod image_codec;
+
+mod export;
+use export::{ExportFormat, ExportOptions};
+
+mod metadata;
+
+mod session;
+use session::SessionImage;
+
+mod history;
+use history::{AdjustmentKey, Command, History, PaletteState};
+
+mod preferences;
+use preferences::Preferences;
+
+mod layout;
+use layout::{Rect, GRID_SIZES};
+
+mod overlay;
+use overlay::Overlay;
+
+mod project;
+use project::{ImageSource, Project, ProjectError, ProjectImage, PROJECT_VERSION};
+
+mod color_space;
+mod point_list;
+mod curves;
+use curves::{Curve, CurvePoint, Levels};
+mod gradient;
+use gradient::{Gradient, GradientStop};
 mod color_management;
-use color_management::*;
+use color_management::{ColorList, ContrastLevel, PaletteSource};
+
+mod palette_format;
+use palette_format::PaletteFormat;
+
+mod lut;
+use lut::{Lut, LUT_MIME};
+
+mod histogram;
+use histogram::{Channel, ChannelStats, Histogram};
 
 mod render_pipeline;
 use render_pipeline::{run, WebImage, FrontendEvent};
 
+mod effects;
+use effects::{Adjustment, Adjustments, Effect, EffectParam, EffectStack, MAX_LAYERS};
+use hex_color::HexColor;
+
 #[allow(non_snake_case)]
 
 fn main() {
@@ -38,395 +86,2007 @@ fn dims(id: &str) -> (f64, f64) {
     (parent_rect.width(), parent_rect.height())
 }
 
-fn prepare_img(cx: Scope, proxy: &Option<EventLoopProxy<FrontendEvent>>) {
-    cx.spawn({
-        let proxy_2 = proxy.clone();
-        async move {
-            if let Some(file) = get_file("img") {
-                let n = canvas_decode(file).await;
-                match n {
-                    Some(img) => send_img(&proxy_2, img),
-                    _ => ()
+// atom access outside of render, for the document listeners and history
+fn read_atom<V: 'static>(root: &AtomRoot, atom: Atom<V>) -> Rc<V> {
+    root.read(atom)
+}
+
+fn write_atom<V: 'static>(root: &AtomRoot, atom: Atom<V>, value: V) {
+    root.initialize(atom);
+    root.set(atom.unique_id(), value);
+}
+
+fn send_root_event(root: &AtomRoot, event: FrontendEvent) {
+    if let Some(proxy) = &*read_atom(root, PROXY) {
+        let _ = proxy.send_event(event);
+    }
+}
+
+// decodes picked, dropped or pasted files into the session and hands them
+// to the renderer. Goes through the atom root rather than values read
+// during render, so the document listeners see the current decoder and proxy.
+fn load_files(root: Rc<AtomRoot>, mut files: Vec<File>) {
+    let decoder = *read_atom(&root, DECODER);
+    write_atom(&root, DECODE_ERROR, None);
+
+    spawn_local(async move {
+        // a project opened along with its referenced images
+        if let Some((index, bytes)) = find_project(&files).await {
+            files.remove(index);
+            if let Err(e) = open_project(&root, &bytes, files, decoder).await {
+                log::error!("couldn't open project: {:?}", e);
+                write_atom(&root, DECODE_ERROR, Some(e.to_string()));
+            }
+            return;
+        }
+
+        // one at a time, so the session keeps the order the files came in
+        for file in files {
+            let name = file.name();
+            match decode_file(file, decoder).await {
+                Ok(img) => add_image(&root, name, img),
+                Err(e) => {
+                    log::error!("decode of {} failed: {:?}", name, e);
+                    write_atom(&root, DECODE_ERROR, Some(format!("{}: {}", name, e)));
+                }
+            };
+        }
+    });
+}
+
+// index and contents of the first project among `files`
+async fn find_project(files: &[File]) -> Option<(usize, Vec<u8>)> {
+    for (index, file) in files.iter().enumerate() {
+        if !project::is_project_name(&file.name()) {
+            continue;
+        }
+        if let Ok(bytes) = read_file(file).await {
+            if project::is_project_file(&bytes) {
+                return Some((index, bytes));
+            }
+        }
+    }
+    None
+}
+
+// new images start from the active image's effects, like a single image
+// session always has
+fn add_image(root: &AtomRoot, name: String, img: WebImage) {
+    let id = *read_atom(root, NEXT_IMAGE_ID);
+    write_atom(root, NEXT_IMAGE_ID, id + 1);
+
+    let effects = (*read_atom(root, EFFECTS)).clone();
+    let adjustments = *read_atom(root, ADJUSTMENTS);
+    let lut = (*read_atom(root, LUT)).clone();
+    let image = SessionImage::new(id, name, &img, effects, adjustments, lut);
+    let previous = *read_atom(root, ACTIVE_IMAGE);
+
+    send_root_event(root, FrontendEvent::NewImage(img));
+    push_image(root, image.clone());
+    record(root, Command::LoadImage { image: Box::new(image), previous });
+}
+
+// appends an image the renderer already has and makes it active
+fn push_image(root: &AtomRoot, image: SessionImage) {
+    let mut session = (*read_atom(root, SESSION)).clone();
+    stash_settings(root, &mut session);
+    session.push(image);
+    write_atom(root, SESSION, session);
+    activate_image(root, Some(read_atom(root, SESSION).len() - 1));
+}
+
+// keeps the current effects with the active image before switching away
+fn stash_settings(root: &AtomRoot, session: &mut [SessionImage]) {
+    if let Some(current) = read_atom(root, ACTIVE_IMAGE).and_then(|i| session.get_mut(i)) {
+        current.effects = (*read_atom(root, EFFECTS)).clone();
+        current.adjustments = *read_atom(root, ADJUSTMENTS);
+        current.lut = (*read_atom(root, LUT)).clone();
+    }
+}
+
+// shows the session image at `index` with the settings kept for it, `None` clears the canvas
+fn activate_image(root: &AtomRoot, index: Option<usize>) {
+    let mut session = (*read_atom(root, SESSION)).clone();
+    stash_settings(root, &mut session);
+    write_atom(root, SESSION, session.clone());
+
+    let target = index.and_then(|i| session.get(i));
+    let effects = target.map(|image| image.effects.clone()).unwrap_or_default();
+    let adjustments = target.map(|image| image.adjustments).unwrap_or_default();
+    let lut = target.and_then(|image| image.lut.clone());
+
+    write_atom(root, EFFECTS, effects.clone());
+    write_atom(root, ADJUSTMENTS, adjustments);
+    write_atom(root, LUT, lut.clone());
+    write_atom(root, SELECTED_LAYER, 0);
+    write_atom(root, ACTIVE_IMAGE, index);
+    send_root_event(root, FrontendEvent::SelectImage(target.map(|image| image.id), effects, adjustments, lut));
+}
+
+// the session as a project, images referenced until the renderer embeds them
+fn current_project(root: &AtomRoot) -> Project {
+    let mut session = (*read_atom(root, SESSION)).clone();
+    stash_settings(root, &mut session);
+
+    let palette_source = *read_atom(root, PALETTE_SOURCE);
+    let palette = match palette_source {
+        PaletteSource::Saved(i) => read_atom(root, SAVED_PALETTES).get(i).cloned(),
+        _ => None,
+    };
+
+    Project {
+        version: PROJECT_VERSION,
+        images: session.into_iter().map(|image| ProjectImage {
+            name: image.name,
+            effects: image.effects,
+            adjustments: image.adjustments,
+            lut: image.lut.map(|lut| lut.encode()),
+            source: ImageSource::Referenced,
+            id: image.id,
+        }).collect(),
+        active: *read_atom(root, ACTIVE_IMAGE),
+        fill: *read_atom(root, FILL),
+        color_index: *read_atom(root, COLOR_INDEX),
+        palette_source,
+        palette,
+        readable_colors: *read_atom(root, READABLE_COLORS),
+        panels: (*read_atom(root, POSITIONS)).clone(),
+    }
+}
+
+fn save_project(cx: Scope) {
+    let root = use_atom_root(&cx);
+    let embed = *read_atom(root, EMBED_IMAGES);
+    send_root_event(root, FrontendEvent::SaveProject(current_project(root), embed));
+}
+
+// decodes every image before touching the session, so a bad project leaves the current one alone
+async fn open_project(root: &AtomRoot, bytes: &[u8], mut files: Vec<File>, decoder: Decoder) -> Result<(), ProjectError> {
+    let project = Project::parse(bytes)?;
+
+    let mut decoded = Vec::with_capacity(project.images.len());
+    let mut luts = Vec::with_capacity(project.images.len());
+    for image in &project.images {
+        luts.push(image.decode_lut()?);
+        let img = match image.decode_embedded() {
+            Some(img) => img?,
+            None => {
+                let index = files.iter().position(|file| file.name() == image.name)
+                    .ok_or_else(|| ProjectError::MissingImage(image.name.clone()))?;
+                decode_file(files.remove(index), decoder).await
+                    .map_err(|e| ProjectError::Image(image.name.clone(), e))?
+            }
+        };
+        decoded.push(img);
+    }
+
+    let previous = (*read_atom(root, SESSION)).clone();
+    let mut history = (*read_atom(root, HISTORY)).clone();
+
+    let mut session = Vec::with_capacity(decoded.len());
+    for ((image, img), lut) in project.images.into_iter().zip(decoded).zip(luts) {
+        let id = *read_atom(root, NEXT_IMAGE_ID);
+        write_atom(root, NEXT_IMAGE_ID, id + 1);
+        session.push(SessionImage::new(id, image.name, &img, image.effects, image.adjustments, lut));
+        send_root_event(root, FrontendEvent::NewImage(img));
+    }
+
+    // the old session and anything it could undo back to is gone
+    for image in previous.iter().chain(&history.clear()) {
+        image.release();
+        send_root_event(root, FrontendEvent::ReleaseImage(image.id));
+    }
+    write_atom(root, HISTORY, history);
+
+    write_atom(root, SESSION, session);
+    write_atom(root, ACTIVE_IMAGE, None);
+    activate_image(root, project.active);
+
+    write_atom(root, FILL, project.fill);
+    send_root_event(root, FrontendEvent::SetFillMode(project.fill));
+    // saved palettes are per browser, the project's is added to this one's
+    let palette_source = match (project.palette_source, project.palette) {
+        (PaletteSource::Saved(_), Some(palette)) => PaletteSource::Saved(add_saved_palette(root, palette)),
+        (PaletteSource::Saved(_), None) => PaletteSource::BuiltIn,
+        (source, _) => source,
+    };
+    write_atom(root, PALETTE_SOURCE, palette_source);
+    write_atom(root, READABLE_COLORS, project.readable_colors);
+    write_atom(root, COLOR_INDEX, project.color_index);
+    let mut panels = (*read_atom(root, POSITIONS)).clone();
+    panels.extend(project.panels);
+    write_atom(root, POSITIONS, panels);
+    Ok(())
+}
+
+fn prepare_img(cx: Scope) {
+    load_files(use_atom_root(&cx).clone(), get_files("img"));
+}
+
+fn select_image(cx: Scope, index: usize) {
+    let root = use_atom_root(&cx);
+    let active = *read_atom(root, ACTIVE_IMAGE);
+
+    if active == Some(index) || index >= read_atom(root, SESSION).len() {
+        return;
+    }
+    activate_image(root, Some(index));
+    record(root, Command::SelectImage { from: active, to: index });
+}
+
+fn toggle_fill(cx: Scope) {
+    let root = use_atom_root(&cx);
+    flip_fill(root);
+    record(root, Command::FillMode);
+}
+
+fn flip_fill(root: &AtomRoot) {
+    write_atom(root, FILL, !*read_atom(root, FILL));
+    send_root_event(root, FrontendEvent::FILL_MODE);
+}
+
+// adds an applied edit to the history, freeing images that can't be redone any more
+fn record(root: &AtomRoot, command: Command) {
+    let mut history = (*read_atom(root, HISTORY)).clone();
+    for image in history.record(command) {
+        image.release();
+        send_root_event(root, FrontendEvent::ReleaseImage(image.id));
+    }
+    write_atom(root, HISTORY, history);
+}
+
+// the slider or color picker driving the last step was let go
+fn end_merge(root: &AtomRoot) {
+    let mut history = (*read_atom(root, HISTORY)).clone();
+    history.end_merge();
+    write_atom(root, HISTORY, history);
+}
+
+// records a button's edit as a step of its own, for edits that share a slider's merge key
+fn edit_step(root: &AtomRoot, edit: impl FnOnce()) {
+    end_merge(root);
+    edit();
+    end_merge(root);
+}
+
+fn undo(root: &AtomRoot) {
+    let mut history = (*read_atom(root, HISTORY)).clone();
+    let command = history.undo();
+    write_atom(root, HISTORY, history);
+
+    match command {
+        Some(Command::Effects { before, .. }) => restore_effects(root, before),
+        Some(Command::Adjustments { before, .. }) => restore_adjustments(root, *before),
+        Some(Command::FillMode) => flip_fill(root),
+        Some(Command::Palette { before, .. }) => restore_palette(root, before),
+        Some(Command::Lut { before, .. }) => restore_lut(root, before),
+        Some(Command::LoadImage { previous, .. }) => {
+            // the loaded image is always the last one, anything after it was undone first
+            let mut session = (*read_atom(root, SESSION)).clone();
+            session.pop();
+            write_atom(root, SESSION, session);
+            write_atom(root, ACTIVE_IMAGE, None);
+            activate_image(root, previous);
+        }
+        Some(Command::SelectImage { from, .. }) => activate_image(root, from),
+        None => (),
+    }
+}
+
+fn redo(root: &AtomRoot) {
+    let mut history = (*read_atom(root, HISTORY)).clone();
+    let command = history.redo();
+    write_atom(root, HISTORY, history);
+
+    match command {
+        Some(Command::Effects { after, .. }) => restore_effects(root, after),
+        Some(Command::Adjustments { after, .. }) => restore_adjustments(root, *after),
+        Some(Command::FillMode) => flip_fill(root),
+        Some(Command::Palette { after, .. }) => restore_palette(root, after),
+        Some(Command::Lut { after, .. }) => restore_lut(root, after),
+        Some(Command::LoadImage { image, .. }) => push_image(root, *image),
+        Some(Command::SelectImage { to, .. }) => activate_image(root, Some(to)),
+        None => (),
+    }
+}
+
+fn restore_effects(root: &AtomRoot, effects: EffectStack) {
+    let selected = *read_atom(root, SELECTED_LAYER);
+    write_atom(root, SELECTED_LAYER, selected.min(effects.layers().len().saturating_sub(1)));
+    write_atom(root, EFFECTS, effects.clone());
+    send_root_event(root, FrontendEvent::SetEffects(effects));
+}
+
+fn restore_adjustments(root: &AtomRoot, adjustments: Adjustments) {
+    write_atom(root, ADJUSTMENTS, adjustments);
+    send_root_event(root, FrontendEvent::SetAdjustments(adjustments));
+}
+
+fn restore_lut(root: &AtomRoot, lut: Option<Lut>) {
+    write_atom(root, LUT, lut.clone());
+    send_root_event(root, FrontendEvent::SetLut(lut));
+}
+
+fn send_shader_event(cx: Scope, event: FrontendEvent) {
+    let proxy = use_read(&cx, PROXY);
+
+    if let Some(proxy) = proxy {
+        proxy.send_event(event);
+    }
+}
+
+// applies `edit` to the UI copy of the effect stack, sends the matching event to the renderer and records it
+// returns whether the stack changed
+fn edit_effects(cx: Scope, edit: impl FnOnce(&mut EffectStack), event: FrontendEvent) -> bool {
+    let effects = use_read(&cx, EFFECTS);
+    let set_effects = use_set(&cx, EFFECTS);
+    let root = use_atom_root(&cx);
+
+    let mut updated = effects.clone();
+    edit(&mut updated);
+    // a full stack or an out of range index, nothing to send or undo
+    if updated == *effects {
+        return false;
+    }
+    let merge = match &event {
+        FrontendEvent::SetEffectParam(index, param) => Some((*index, std::mem::discriminant(param))),
+        _ => None,
+    };
+    let command = Command::Effects { before: effects.clone(), after: updated.clone(), merge };
+
+    set_effects(updated);
+    send_shader_event(cx, event);
+    record(root, command);
+    true
+}
+
+fn step_effect(cx: Scope) {
+    let selected = *use_read(&cx, SELECTED_LAYER);
+    edit_effects(cx, |stack| stack.step(selected), FrontendEvent::StepEffect(selected));
+}
+
+fn set_effect_param(cx: Scope, param: EffectParam) {
+    let selected = *use_read(&cx, SELECTED_LAYER);
+    edit_effects(cx, |stack| stack.set(selected, param), FrontendEvent::SetEffectParam(selected, param));
+}
+
+// edits the selected layer's gradient map stops
+fn edit_gradient(cx: Scope, edit: impl FnOnce(&mut Gradient)) {
+    let selected = *use_read(&cx, SELECTED_LAYER);
+    let mut gradient = match use_read(&cx, EFFECTS).get(selected) {
+        Some(layer) => *layer.settings.gradient(),
+        None => return,
+    };
+    edit(&mut gradient);
+    set_effect_param(cx, EffectParam::Gradient(gradient));
+}
+
+fn add_effect(cx: Scope) {
+    let effects = use_read(&cx, EFFECTS);
+    let set_selected = use_set(&cx, SELECTED_LAYER);
+
+    let top = effects.layers().len();
+    if top >= MAX_LAYERS {
+        return;
+    }
+    edit_effects(cx, |stack| stack.add(Effect::Mask), FrontendEvent::AddEffect(Effect::Mask));
+    set_selected(top);
+}
+
+fn remove_effect(cx: Scope, index: usize) {
+    let selected = *use_read(&cx, SELECTED_LAYER);
+    let set_selected = use_set(&cx, SELECTED_LAYER);
+
+    let removed = edit_effects(cx, |stack| stack.remove(index), FrontendEvent::RemoveEffect(index));
+    if removed && selected >= index && selected > 0 {
+        set_selected(selected - 1);
+    }
+}
+
+fn move_effect(cx: Scope, from: usize, to: usize) {
+    let set_selected = use_set(&cx, SELECTED_LAYER);
+
+    if edit_effects(cx, |stack| stack.move_layer(from, to), FrontendEvent::MoveEffect(from, to)) {
+        set_selected(to);
+    }
+}
+
+// a slider edit, a drag of it becomes one step
+fn set_adjustment(cx: Scope, adjustment: Adjustment) {
+    edit_adjustments(cx, adjustment, Some(AdjustmentKey::new(&adjustment, None)));
+}
+
+// applies `adjustment` to the UI copy, sends it to the renderer and records it
+// reads the atom root rather than the render's copy, a curve drag can
+// send several edits before the panel renders again
+fn edit_adjustments(cx: Scope, adjustment: Adjustment, merge: Option<AdjustmentKey>) {
+    let root = use_atom_root(&cx);
+    let adjustments = read_atom(root, ADJUSTMENTS);
+    let set_adjustments = use_set(&cx, ADJUSTMENTS);
+
+    let mut updated = *adjustments;
+    updated.set(adjustment);
+    if updated == *adjustments {
+        return;
+    }
+    let command = Command::Adjustments {
+        before: Box::new(*adjustments),
+        after: Box::new(updated),
+        merge,
+    };
+
+    set_adjustments(updated);
+    send_shader_event(cx, FrontendEvent::SetAdjustment(adjustment));
+    record(root, command);
+}
+
+// adding, removing or resetting points, each its own step
+fn edit_curve(cx: Scope, channel: Channel, edit: impl FnOnce(&mut Curve)) {
+    let mut curve = *read_atom(use_atom_root(&cx), ADJUSTMENTS).curves.curve(channel);
+    edit(&mut curve);
+    edit_adjustments(cx, Adjustment::Curve(channel, curve), None);
+}
+
+// a drag of one point, merged until it's let go
+fn move_curve_point(cx: Scope, channel: Channel, index: usize, point: CurvePoint) {
+    let mut curve = *read_atom(use_atom_root(&cx), ADJUSTMENTS).curves.curve(channel);
+    curve.set(index, point);
+    let adjustment = Adjustment::Curve(channel, curve);
+    edit_adjustments(cx, adjustment, Some(AdjustmentKey::new(&adjustment, Some(index))));
+}
+
+static PROXY: Atom<Option<EventLoopProxy<FrontendEvent>>> = |_| None;
+static PIPELINE_STATUS: Atom<bool> = |_| false;
+static INITIAL_PAGE_LOAD: Atom<bool> = |_| false;
+static EFFECTS: Atom<EffectStack> = |_| preferences::stored().effects;
+static SELECTED_LAYER: Atom<usize> = |_| 0;
+static DECODER: Atom<Decoder> = |_| Decoder::Canvas;
+static DECODE_ERROR: Atom<Option<String>> = |_| None;
+static EXPORT_OPTIONS: Atom<ExportOptions> = |_| ExportOptions::default();
+static EXPORT_DIALOG: Atom<bool> = |_| false;
+// draw the panels into the export, captured when it starts
+static EXPORT_PANELS: Atom<bool> = |_| false;
+// every loaded image, exports are named after the active one
+static SESSION: Atom<Vec<SessionImage>> = |_| Vec::new();
+static ACTIVE_IMAGE: Atom<Option<usize>> = |_| None;
+// renderer side id of the next loaded image, ids aren't reused
+static NEXT_IMAGE_ID: Atom<usize> = |_| 0;
+static HISTORY: Atom<History> = |_| History::new();
+static ADJUSTMENTS: Atom<Adjustments> = |_| preferences::stored().adjustments;
+
+static POSITION: Atom<(f64, f64)> = |_| (40f64, 40f64);
+
+static POSITIONS: Atom<HashMap<String, (f64, f64)>> = |_| preferences::stored().panels;
+static COLOR_INDEX: Atom<usize> = |_| preferences::stored().color_index;
+static PALETTE_SOURCE: Atom<PaletteSource> = |_| preferences::stored().palette_source;
+// imported palettes, `PaletteSource::Saved` indexes into these
+static SAVED_PALETTES: Atom<Vec<ColorList>> = |_| preferences::stored().palettes;
+static PALETTE_ERROR: Atom<Option<String>> = |_| None;
+// lighten or darken palette colors that fail WCAG AA, see `ColorEntry::readable`
+static READABLE_COLORS: Atom<bool> = |_| preferences::stored().readable_colors;
+// the active image's .cube, kept with it in the session and saved with projects
+static LUT: Atom<Option<Lut>> = |_| None;
+static LUT_ERROR: Atom<Option<String>> = |_| None;
+// of the rendered output, sent back by the renderer after each change
+static OUTPUT_HISTOGRAM: Atom<Option<Histogram>> = |_| None;
+static HISTOGRAM_CHANNEL: Atom<Channel> = |_| Channel::Rgb;
+static CURVE_CHANNEL: Atom<Channel> = |_| Channel::Rgb;
+// ids of the panels showing only their header
+static COLLAPSED: Atom<HashSet<String>> = |_| preferences::stored().collapsed;
+// ids of the panels stacked in the rail rather than floating
+static DOCKED: Atom<HashSet<String>> = |_| preferences::stored().docked;
+// snap grid for dragged panels in pixels, 0 for none
+static GRID: Atom<u32> = |_| preferences::stored().grid;
+// UI copy of the renderer's fill mode, saved with projects
+static FILL: Atom<bool> = |_| preferences::stored().fill;
+// whether saved projects carry their images or only their names
+static EMBED_IMAGES: Atom<bool> = |_| true;
+
+
+fn start(cx: Scope) {
+    let pipeline_status = use_read(&cx, PIPELINE_STATUS);
+    let set_pipeline_status = use_set(&cx, PIPELINE_STATUS);
+    let set_proxy = use_set(&cx, PROXY);
+    let initial_load = use_read(&cx, INITIAL_PAGE_LOAD);
+    let set_initial_load = use_set(&cx, INITIAL_PAGE_LOAD);
+    let effects = use_read(&cx, EFFECTS);
+    let adjustments = *use_read(&cx, ADJUSTMENTS);
+    let fill = *use_read(&cx, FILL);
+    let root = use_atom_root(&cx).clone();
+
+
+    if !pipeline_status && *initial_load {
+        let (view_width, view_height) = dims("parent");
+        let proxy = run(view_width, view_height, move |histogram| write_atom(&root, OUTPUT_HISTOGRAM, Some(histogram)));
+        // the renderer starts from defaults, bring it in line with the restored preferences
+        let _ = proxy.send_event(FrontendEvent::SetEffects(effects.clone()));
+        let _ = proxy.send_event(FrontendEvent::SetAdjustments(adjustments));
+        let _ = proxy.send_event(FrontendEvent::SetFillMode(fill));
+        set_proxy(Some(proxy));
+        set_pipeline_status(true);
+    } 
+
+    if !initial_load {
+        set_initial_load(true);
+    }
+}
+
+struct DraggableState {
+    id: String,
+    start_pos: (f64, f64),
+}
+
+/// A draggable tool panel. `position` is the default top left corner as
+/// a fraction of the viewport, used until the panel is moved.
+struct Panel {
+    id: &'static str,
+    title: &'static str,
+    position: (f64, f64),
+    content: Component,
+}
+
+fn panel_element_id(id: &str) -> String {
+    format!("panel-{}", id)
+}
+
+// where an element is on screen, `None` until it has rendered
+fn element_rect(id: &str) -> Option<Rect> {
+    get_optional_element_by_id(id).map(|el| {
+        let rect = el.get_bounding_client_rect();
+        Rect { x: rect.x(), y: rect.y(), width: rect.width(), height: rect.height() }
+    })
+}
+
+// the palette the color panel cycles, the built-in one when there's no image to take it from
+fn active_palette(source: PaletteSource, saved: &[ColorList], session: &[SessionImage], active: Option<usize>, readable: bool) -> ColorList {
+    let palette = match (source, active.and_then(|i| session.get(i))) {
+        (PaletteSource::Image, Some(image)) => image.palette.clone(),
+        (PaletteSource::Saved(i), _) => saved.get(i).cloned().unwrap_or_else(ColorList::new),
+        _ => ColorList::new(),
+    };
+    if readable { palette.readable() } else { palette }
+}
+
+// adds every palette that parses to the saved ones and shows the last
+fn import_palettes(root: Rc<AtomRoot>, files: Vec<File>) {
+    write_atom(&root, PALETTE_ERROR, None);
+    spawn_local(async move {
+        for file in files {
+            let name = file.name();
+            let palette = match read_file(&file).await {
+                Ok(bytes) => palette_format::parse(&bytes, &name).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match palette {
+                Ok(palette) => {
+                    let index = add_saved_palette(&root, palette);
+                    edit_palette(&root, |state| {
+                        state.source = PaletteSource::Saved(index);
+                        state.color_index = 0;
+                    });
+                }
+                Err(e) => {
+                    log::error!("import of {} failed: {}", name, e);
+                    write_atom(&root, PALETTE_ERROR, Some(format!("{}: {}", name, e)));
+                }
+            }
+        }
+    });
+}
+
+// index of `palette` among the saved ones, added unless it's already there
+fn add_saved_palette(root: &AtomRoot, palette: ColorList) -> usize {
+    let mut saved = (*read_atom(root, SAVED_PALETTES)).clone();
+    if let Some(index) = saved.iter().position(|existing| *existing == palette) {
+        return index;
+    }
+    saved.push(palette);
+    write_atom(root, SAVED_PALETTES, saved);
+    read_atom(root, SAVED_PALETTES).len() - 1
+}
+
+fn import_lut(root: Rc<AtomRoot>, files: Vec<File>) {
+    write_atom(&root, LUT_ERROR, None);
+    let file = match files.into_iter().next() {
+        Some(file) => file,
+        None => return,
+    };
+    spawn_local(async move {
+        let name = file.name();
+        let lut = match read_file(&file).await {
+            Ok(bytes) => Lut::parse(&String::from_utf8_lossy(&bytes), &name).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match lut {
+            Ok(lut) => set_lut(&root, Some(lut)),
+            Err(e) => {
+                log::error!("import of {} failed: {}", name, e);
+                write_atom(&root, LUT_ERROR, Some(format!("{}: {}", name, e)));
+            }
+        }
+    });
+}
+
+fn remove_lut(cx: Scope) {
+    let root = use_atom_root(&cx);
+    write_atom(root, LUT_ERROR, None);
+    set_lut(root, None);
+}
+
+// swaps the active image's LUT and records it, reloading the same one isn't a step
+fn set_lut(root: &AtomRoot, lut: Option<Lut>) {
+    let before = (*read_atom(root, LUT)).clone();
+    if before == lut {
+        return;
+    }
+    restore_lut(root, lut.clone());
+    record(root, Command::Lut { before, after: lut });
+}
+
+fn palette_state(root: &AtomRoot) -> PaletteState {
+    PaletteState {
+        source: *read_atom(root, PALETTE_SOURCE),
+        color_index: *read_atom(root, COLOR_INDEX),
+        readable: *read_atom(root, READABLE_COLORS),
+    }
+}
+
+// the renderer follows along through `PaletteSync`
+fn restore_palette(root: &AtomRoot, state: PaletteState) {
+    write_atom(root, PALETTE_SOURCE, state.source);
+    write_atom(root, COLOR_INDEX, state.color_index);
+    write_atom(root, READABLE_COLORS, state.readable);
+}
+
+// applies `edit` to the palette settings and records it
+fn edit_palette(root: &AtomRoot, edit: impl FnOnce(&mut PaletteState)) {
+    let before = palette_state(root);
+    let mut after = before;
+    edit(&mut after);
+    if after == before {
+        return;
+    }
+    restore_palette(root, after);
+    record(root, Command::Palette { before, after });
+}
+
+// not a step of its own, the saved palettes are kept apart from the edits
+fn delete_palette(cx: Scope, index: usize) {
+    let root = use_atom_root(&cx);
+    let mut saved = (*read_atom(root, SAVED_PALETTES)).clone();
+    if index >= saved.len() {
+        return;
+    }
+    saved.remove(index);
+    write_atom(root, SAVED_PALETTES, saved);
+    write_atom(root, PALETTE_SOURCE, read_atom(root, PALETTE_SOURCE).without_saved(index));
+
+    let mut history = (*read_atom(root, HISTORY)).clone();
+    history.remove_saved_palette(index);
+    write_atom(root, HISTORY, history);
+}
+
+// the panels as they are on screen, relative to the canvas, for the export to draw
+fn capture_overlay() -> Option<Overlay> {
+    let canvas = get_optional_element_by_id("canvas")?;
+    let panels: Vec<web_sys::Element> = PANELS.iter()
+        .filter_map(|panel| get_optional_element_by_id(&panel_element_id(panel.id)))
+        .collect();
+    Overlay::capture(&canvas, &panels)
+}
+
+// every panel on the page, adding one here is all it takes to show it
+const PANELS: &[Panel] = &[
+    Panel { id: "source", title: "Image", position: (0.02, 0.05), content: SourcePanel },
+    Panel { id: "palette", title: "Palette", position: (0.02, 0.6), content: PalettePanel },
+    Panel { id: "light", title: "Light", position: (0.14, 0.05), content: AdjustmentControls },
+    Panel { id: "histogram", title: "Histogram", position: (0.14, 0.6), content: HistogramPanel },
+    Panel { id: "curves", title: "Curves", position: (0.26, 0.6), content: CurvesPanel },
+    Panel { id: "layers", title: "Layers", position: (0.26, 0.05), content: EffectStackControls },
+    Panel { id: "effect", title: "Effect", position: (0.38, 0.05), content: EffectPanel },
+    Panel { id: "images", title: "Images", position: (0.5, 0.7), content: Filmstrip },
+    Panel { id: "history", title: "History", position: (0.86, 0.05), content: HistoryControls },
+    Panel { id: "file", title: "File", position: (0.86, 0.4), content: FilePanel },
+    Panel { id: "layout", title: "Layout", position: (0.86, 0.75), content: LayoutControls },
+];
+
+fn app(cx: Scope) -> Element {
+    log::info!("updating ui");
+    start(cx);
+
+    let root = use_atom_root(&cx).clone();
+    cx.use_hook(|_| {
+        // dropping on `main` covers the whole page, panels included
+        if let Some(main) = get_optional_element_by_id("main") {
+            listen_for_files(&main, {
+                let root = root.clone();
+                move |files| load_files(root.clone(), files)
+            });
+        }
+        listen_for_undo({
+            let root = root.clone();
+            move || undo(&root)
+        }, move || redo(&root));
+    });
+
+    // Position on the square where drag was started
+    // If we are not currently dragging, `None`
+    let active_draggable: &UseState<Option<DraggableState>> = use_state(&cx, || None);
+    let positions = use_read(&cx, POSITIONS);
+    let set_positions = use_set(&cx, POSITIONS);
+    let collapsed = use_read(&cx, COLLAPSED);
+    let set_collapsed = use_set(&cx, COLLAPSED);
+    let docked = use_read(&cx, DOCKED);
+    let set_docked = use_set(&cx, DOCKED);
+    let grid = *use_read(&cx, GRID);
+
+    let (view_width, view_height) = dims("main");
+    // floating panels keep clear of the rail
+    let rail_width = element_rect("dock-rail").map_or(0.0, |rail| rail.width);
+    let view = (view_width - rail_width, view_height);
+
+    let mut layout = positions.clone();
+    for panel in PANELS {
+        layout.entry(panel.id.to_string()) // set position to initial value
+            .or_insert((panel.position.0 * view_width, panel.position.1 * view_height));
+    }
+    if layout.len() != positions.len() {
+        set_positions(layout.clone());
+    }
+
+    // the other floating panels, as they are on screen
+    let obstacles = move |except: &str| -> Vec<Rect> {
+        PANELS.iter()
+            .filter(|panel| panel.id != except && !docked.contains(panel.id))
+            .filter_map(|panel| element_rect(&panel_element_id(panel.id)))
+            .collect()
+    };
+
+    // When user starts dragging, track where on the square they started
+    let mouse_down_handler =
+        move |event: UiEvent<PointerData>, el: String| {
+            log::info!("mouse down!");
+            // the rendered corner, which may be clamped or in the rail
+            let pos = match element_rect(&panel_element_id(&el)) {
+                Some(rect) => (rect.x, rect.y),
+                None => *positions.get(&el).expect("missing position"),
+            };
+            // dragging a docked panel pulls it out of the rail
+            if docked.contains(&el) {
+                let mut undocked = docked.clone();
+                undocked.remove(&el);
+                set_docked(undocked);
+                let mut moved = positions.clone();
+                moved.insert(el.clone(), pos);
+                set_positions(moved);
+            }
+            active_draggable.set(Some(DraggableState {
+                start_pos: (event.data.page_x as f64 - pos.0, 
+                    event.data.page_y as f64 - pos.1),
+                id: el,
+            }));
+            
+        };
+    
+    // When the mouse moves on the container
+    let mouse_move_handler = move |event: UiEvent<PointerData>| {
+        // If we are currently dragging the square
+        if let Some(active) = &**active_draggable {
+            // Calculate the new coordinates
+            // (Offset by the square coordinates we started dragging on, otherwise, we would drag the top-left corner)
+            let (s_x, s_y) = active.start_pos;
+            let x = event.data.page_x as f64 - s_x;
+            let y = event.data.page_y as f64 - s_y;
+
+            let pos = match element_rect(&panel_element_id(&active.id)) {
+                Some(rect) => layout::snap(Rect { x, y, ..rect }, &obstacles(&active.id), view, grid),
+                None => (x, y),
+            };
+            let mut moved = positions.clone();
+            moved.insert(active.id.clone(), pos);
+            set_positions(moved);
+        }
+    };
+    
+    // When mouse is released, stop dragging
+    let mouse_up_handler = move |event: UiEvent<PointerData>| {
+        if let Some(active) = &**active_draggable {
+            if layout::in_dock_zone(event.data.page_x as f64, view.0) {
+                let mut updated = docked.clone();
+                updated.insert(active.id.clone());
+                set_docked(updated);
+            } else if let Some(rect) = element_rect(&panel_element_id(&active.id)) {
+                let mut moved = positions.clone();
+                moved.insert(active.id.clone(), layout::avoid_overlap(rect, &obstacles(&active.id), view));
+                set_positions(moved);
+            }
+        }
+        active_draggable.set(None);
+    };
+
+    let render_panel = move |panel: &'static Panel| {
+        let id = panel.id;
+        let content = panel.content;
+        let is_collapsed = collapsed.contains(id);
+        let is_docked = docked.contains(id);
+        let size = element_rect(&panel_element_id(id)).map_or((0.0, 0.0), |rect| (rect.width, rect.height));
+        // a panel saved on a larger screen is pulled back into view
+        let pos = layout::clamp(Rect::new(layout[id], size), view);
+        let body = LazyNodes::new(move |f| f.component(content, (), None, "panel content"));
+        rsx!(
+            Draggable {
+                key: "{id}",
+                id: id,
+                onpointerdown: move |evt| mouse_down_handler(evt, id.to_string()),
+                pos: pos,
+                title: panel.title,
+                collapsed: is_collapsed,
+                docked: is_docked,
+                ontoggle: move |_| {
+                    let mut updated = collapsed.clone();
+                    if !updated.remove(id) {
+                        updated.insert(id.to_string());
+                    }
+                    set_collapsed(updated);
+                },
+                ondock: move |_| {
+                    let mut updated = docked.clone();
+                    if !updated.remove(id) {
+                        updated.insert(id.to_string());
+                    }
+                    set_docked(updated);
+                },
+                body
+            }
+        )
+    };
+
+    let render_panel = &render_panel;
+    let floating = PANELS.iter()
+        .filter(|panel| !docked.contains(panel.id))
+        .map(render_panel);
+    let rail = PANELS.iter().any(|panel| docked.contains(panel.id)).then(|| rsx!(
+        div {
+            id: "dock-rail",
+            class: "dock-rail",
+            PANELS.iter()
+                .filter(|panel| docked.contains(panel.id))
+                .map(render_panel)
+        }
+    ));
+
+    cx.render(rsx!{ 
+        DecodeCanvas {}
+        DownloadAnchor {}
+        ExportDialog {}
+        PreferenceStore {}
+        PaletteSync {}
+        
+        div {
+            class: "row row-center",
+            Canvas {}
+        }  
+        div {
+            width: "100vw",
+            height: "100vh",
+            style: "overflow: hidden; height: 100vh; width: 100vw; position: absolute; top: 0; left: 0",
+            //u
+            onpointermove: mouse_move_handler,
+            onpointerup: mouse_up_handler,
+            floating
+            rail
+        }
+    })
+}
+
+fn SourcePanel(cx: Scope) -> Element {
+    let decoder = use_read(&cx, DECODER);
+    let set_decoder = use_set(&cx, DECODER);
+    let decoder_name = decoder.name();
+    let decode_error = use_read(&cx, DECODE_ERROR);
+
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            FileInput {
+                file_types: "image",
+                id: "img",
+                oninput: move |_| prepare_img(cx),
+            }
+        }
+        decode_error.as_ref().map(|e| rsx!(
+            p {
+                class: "error",
+                "{e}"
+            }
+        ))
+        div {
+            class: "button-row",
+            VoteButton {
+                name: decoder_name,
+                onclick: move |_| set_decoder(decoder.toggle()),
+            }
+        }
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Fill",
+                onclick: move |_| toggle_fill(cx),
+            }
+        }
+    })
+}
+
+// mean, median, range and clipping, as the stats table shows them
+fn stat_values(stats: &ChannelStats) -> [String; 4] {
+    [
+        format!("{:.1}", stats.mean),
+        stats.median.to_string(),
+        format!("{}-{}", stats.min, stats.max),
+        format!("{:.1}% / {:.1}%", stats.clipped_low * 100.0, stats.clipped_high * 100.0),
+    ]
+}
+
+fn HistogramPanel(cx: Scope) -> Element {
+    let session = use_read(&cx, SESSION);
+    let active = *use_read(&cx, ACTIVE_IMAGE);
+    let output = use_read(&cx, OUTPUT_HISTOGRAM);
+    let channel = *use_read(&cx, HISTOGRAM_CHANNEL);
+    let set_channel = use_set(&cx, HISTOGRAM_CHANNEL);
+    let source = active.and_then(|i| session.get(i)).map(|image| &image.histogram);
+    // the renderer's last output belongs to no image once the canvas is cleared
+    let output = active.and(output.as_ref());
+    let channel_name = channel.name();
+    let channel_color = channel.css();
+
+    let graph = |label: &'static str, histogram: Option<&Histogram>| {
+        let url = histogram.map(|histogram| histogram.data_url());
+        rsx!(
+            p {
+                class: "histogram-label",
+                "{label}"
+            }
+            url.map(|url| rsx!(
+                img {
+                    class: "histogram",
+                    src: "{url}",
+                }
+            ))
+        )
+    };
+    let column = |histogram: Option<&Histogram>| histogram
+        .and_then(|histogram| histogram.stats(channel))
+        .map_or_else(|| [(); 4].map(|_| String::from("-")), |stats| stat_values(&stats));
+    let names = ["Mean", "Median", "Range", "Clipped"];
+    let stats = names.into_iter().zip(column(source)).zip(column(output)).map(|((name, source_value), output_value)| {
+        rsx!(
+            tr {
+                key: "{name}",
+                td { "{name}" }
+                td { "{source_value}" }
+                td { "{output_value}" }
+            }
+        )
+    });
+
+    let source_graph = graph("Source", source);
+    let output_graph = graph("Output", output);
+
+    cx.render(rsx!{
+        source_graph
+        output_graph
+        div {
+            class: "button-row",
+            VoteButton {
+                name: channel_name,
+                onclick: move |_| set_channel(channel.next()),
+            }
+        }
+        table {
+            class: "histogram-stats",
+            style: "border-color: {channel_color}",
+            thead {
+                tr {
+                    th {}
+                    th { "Source" }
+                    th { "Output" }
+                }
+            }
+            tbody {
+                stats
+            }
+        }
+    })
+}
+
+fn PalettePanel(cx: Scope) -> Element {
+    let root = use_atom_root(&cx);
+    let source = *use_read(&cx, PALETTE_SOURCE);
+    let saved = use_read(&cx, SAVED_PALETTES);
+    let palette_error = use_read(&cx, PALETTE_ERROR);
+    let readable = *use_read(&cx, READABLE_COLORS);
+    let readable_name = if readable { "Readable colors" } else { "Exact colors" };
+    let original = active_palette(source, saved, use_read(&cx, SESSION), *use_read(&cx, ACTIVE_IMAGE), false);
+    let failing = original.failing_entries().len();
+    let colors = if readable { original.readable() } else { original };
+    let failing_note = match (failing, readable) {
+        (0, _) => None,
+        (n, true) => Some(format!("{} of {} entries adjusted", n, colors.len())),
+        (n, false) => Some(format!("{} of {} entries fail AA", n, colors.len())),
+    };
+    let saved_count = saved.len();
+    let palette_name = colors.name().to_string();
+    let export_buttons = PaletteFormat::ALL.iter().map(|&format| {
+        let colors = colors.clone();
+        let extension = format.extension();
+        rsx!(
+            VoteButton {
+                key: "{extension}",
+                name: format.name(),
+                onclick: move |_| download_bytes(
+                    &palette_format::encode(&colors, format),
+                    format.mime(),
+                    &palette_format::file_name(&colors, format),
+                ),
+            }
+        )
+    });
+    let saved_index = match source {
+        PaletteSource::Saved(index) => Some(index),
+        _ => None,
+    };
+    //let n = colors.make_mut().next_color();
+    let colors_index = *use_read(&cx, COLOR_INDEX) % colors.len();
+    let active_color = colors.get_color(colors_index);
+    let ink = active_color.accent_ink();
+    let count = colors.len();
+    let text_contrast = active_color.text_contrast();
+    let text_level = ContrastLevel::from_ratio(text_contrast).name();
+    let accent_contrast = active_color.accent_contrast();
+    let accent_level = ContrastLevel::from_ratio(accent_contrast).name();
+
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            div {
+                class: "button-column",
+                VoteButton {
+                    name: "+",
+                    onclick: move |_| edit_palette(root, |state| state.color_index = (colors_index + 1) % count),
+                }
+            }
+            div {
+                class: "button-spacer"
+            }
+            div {
+                class: "button-column",
+                VoteButton {
+                    name: "-",
+                    onclick: move |_| edit_palette(root, |state| state.color_index = (colors_index + count - 1) % count),
+                }
+            }
+        }
+        div {
+            style: "display: table; width: 100%",
+            h6 { 
+                id: "palette-swatch",
+                style: "display: table-cell; vertical-align: middle; width: 100%; text-align: center; height: 3.8rem; background-color: {active_color.accent}; color: {ink}",
+                "{colors_index}" 
+            }
+        }
+        p {
+            class: "contrast-rating",
+            style: "background-color: {active_color.bg_hex}; color: {active_color.fg_hex}",
+            "Text {text_contrast:.1}:1 {text_level}"
+            br {}
+            span {
+                style: "color: {active_color.accent}",
+                "Accent {accent_contrast:.1}:1 {accent_level}"
+            }
+        }
+        failing_note.map(|note| rsx!(
+            p {
+                class: "error",
+                "{note}"
+            }
+        ))
+        div {
+            class: "button-row",
+            VoteButton {
+                name: readable_name,
+                onclick: move |_| edit_palette(root, |state| state.readable = !readable),
+            }
+        }
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "{palette_name}",
+                onclick: move |_| edit_palette(root, |state| state.source = source.next(saved_count)),
+            }
+        }
+        div {
+            class: "button-row",
+            FileInput {
+                file_types: ".json,.gpl,.ase,.hex,.txt",
+                id: "palette-file",
+                label: "Import",
+                oninput: move |_| import_palettes(use_atom_root(&cx).clone(), get_files("palette-file")),
+            }
+        }
+        palette_error.as_ref().map(|e| rsx!(
+            p {
+                class: "error",
+                "{e}"
+            }
+        ))
+        div {
+            class: "button-row",
+            export_buttons
+        }
+        saved_index.map(|index| rsx!(
+            div {
+                class: "button-row",
+                VoteButton {
+                    name: "Delete palette",
+                    onclick: move |_| delete_palette(cx, index),
+                }
+            }
+        ))
+    })
+}
+
+fn EffectPanel(cx: Scope) -> Element {
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Next effect",
+                onclick: move |_| step_effect(cx),
+            }
+        }
+        EffectControls {}
+    })
+}
+
+fn FilePanel(cx: Scope) -> Element {
+    cx.render(rsx!{
+        ExportControls {}
+        ProjectControls {}
+    })
+}
+
+//borrowed props (no partialeq)
+#[derive(Props)] 
+struct VoteButtonProps<'a> {
+    name: &'a str,
+    onclick: EventHandler<'a, MouseEvent>
+}
+
+fn VoteButton<'a>(cx: Scope<'a,VoteButtonProps<'a>>) -> Element {
+    cx.render(rsx!{
+        div {
+            button {
+                class: "button button-outline",
+                width: "100%",
+                onclick: move |evt| cx.props.onclick.call(evt),
+                "{cx.props.name}"
+            }
+        }
+    })
+}
+
+
+
+#[derive(Props)]
+struct SliderProps<'a> {
+    name: &'a str,
+    min: f32,
+    max: f32,
+    value: f32,
+    oninput: EventHandler<'a, f32>
+}
+
+fn Slider<'a>(cx: Scope<'a, SliderProps<'a>>) -> Element {
+    let root = use_atom_root(&cx);
+
+    cx.render(rsx!{
+        div {
+            class: "slider",
+            label {
+                "{cx.props.name}"
+            }
+            input {
+                r#type: "range",
+                min: "{cx.props.min}",
+                max: "{cx.props.max}",
+                step: "0.01",
+                value: "{cx.props.value}",
+                oninput: move |evt: FormEvent| {
+                    if let Ok(value) = evt.value.parse::<f32>() {
+                        cx.props.oninput.call(value);
+                    }
+                },
+                // fired once the slider is let go, so the next drag is a new step
+                onchange: move |_| end_merge(root),
+            }
+        }
+    })
+}
+
+#[derive(Props)]
+struct ColorInputProps<'a> {
+    name: &'a str,
+    value: &'a str,
+    oninput: EventHandler<'a, [f32; 3]>
+}
+
+fn ColorInput<'a>(cx: Scope<'a, ColorInputProps<'a>>) -> Element {
+    let root = use_atom_root(&cx);
+
+    cx.render(rsx!{
+        div {
+            class: "slider",
+            label {
+                "{cx.props.name}"
+            }
+            input {
+                r#type: "color",
+                value: "{cx.props.value}",
+                oninput: move |evt: FormEvent| {
+                    if let Ok(color) = HexColor::parse_rgb(&evt.value) {
+                        cx.props.oninput.call([color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0]);
+                    }
+                },
+                // fired once the picker is closed
+                onchange: move |_| end_merge(root),
+            }
+        }
+    })
+}
+
+fn EffectControls(cx: Scope) -> Element {
+    let effects = use_read(&cx, EFFECTS);
+    let selected = *use_read(&cx, SELECTED_LAYER);
+
+    let layer = match effects.get(selected) {
+        Some(layer) => layer,
+        None => return cx.render(rsx!{
+            h6 {
+                class: "panel-title",
+                "No effect"
+            }
+        })
+    };
+    let params = layer.settings.current();
+    let name = layer.settings.effect.name();
+    let tint = params.tint_hex();
+    let shadow = params.shadow_hex();
+    let midtone = params.midtone_hex();
+    let palette = active_palette(*use_read(&cx, PALETTE_SOURCE), use_read(&cx, SAVED_PALETTES), use_read(&cx, SESSION), *use_read(&cx, ACTIVE_IMAGE), *use_read(&cx, READABLE_COLORS));
+    let preview = layer.gradient(palette.get_color(*use_read(&cx, COLOR_INDEX))).map(|gradient| gradient.css());
+    let edits_stops = layer.settings.effect == Effect::GradientMap && !layer.follow_palette;
+    let root = use_atom_root(&cx);
+    let stops = layer.settings.gradient().stops().iter().copied().enumerate().map(|(i, stop)| {
+        let hex = stop.hex();
+        let number = i + 1;
+        rsx!(
+            div {
+                key: "{i}",
+                class: "gradient-stop",
+                ColorInput {
+                    name: "Stop {number}",
+                    value: "{hex}",
+                    oninput: move |color| edit_gradient(cx, |gradient| gradient.set(i, GradientStop { color, ..stop })),
+                }
+                Slider {
+                    name: "Position",
+                    min: 0.0,
+                    max: 1.0,
+                    value: stop.position,
+                    oninput: move |position| edit_gradient(cx, |gradient| gradient.set(i, GradientStop { position, ..stop })),
+                }
+                VoteButton {
+                    name: "x",
+                    onclick: move |_| edit_step(root, || edit_gradient(cx, |gradient| gradient.remove_stop(i))),
+                }
+            }
+        )
+    });
+    let colors_name = if layer.follow_palette { "Palette colors" } else { "Own colors" };
+
+    cx.render(rsx!{
+        h6 {
+            class: "panel-title",
+            "{name}"
+        }
+        Slider {
+            name: "Intensity",
+            min: 0.0,
+            max: 1.0,
+            value: params.intensity,
+            oninput: move |v| set_effect_param(cx, EffectParam::Intensity(v)),
+        }
+        Slider {
+            name: "Threshold",
+            min: 0.0,
+            max: 1.0,
+            value: params.threshold,
+            oninput: move |v| set_effect_param(cx, EffectParam::Threshold(v)),
+        }
+        Slider {
+            name: "Offset",
+            min: 0.0,
+            max: 1.0,
+            value: params.offset,
+            oninput: move |v| set_effect_param(cx, EffectParam::Offset(v)),
+        }
+        Slider {
+            name: "Amount",
+            min: 0.0,
+            max: 16.0,
+            value: params.amount,
+            oninput: move |v| set_effect_param(cx, EffectParam::Amount(v)),
+        }
+        ColorInput {
+            name: "Tint",
+            value: "{tint}",
+            oninput: move |rgb| set_effect_param(cx, EffectParam::Tint(rgb)),
+        }
+        ColorInput {
+            name: "Shadow",
+            value: "{shadow}",
+            oninput: move |rgb| set_effect_param(cx, EffectParam::Shadow(rgb)),
+        }
+        ColorInput {
+            name: "Midtone",
+            value: "{midtone}",
+            oninput: move |rgb| set_effect_param(cx, EffectParam::Midtone(rgb)),
+        }
+        preview.map(|css| rsx!(
+            div {
+                class: "gradient-preview",
+                style: "background: {css}",
+            }
+        ))
+        edits_stops.then(|| rsx!(
+            stops
+            div {
+                class: "button-row",
+                VoteButton {
+                    name: "Add stop",
+                    onclick: move |_| edit_step(root, || edit_gradient(cx, |gradient| gradient.add_stop())),
+                }
+            }
+        ))
+        div {
+            class: "button-row",
+            VoteButton {
+                name: colors_name,
+                onclick: move |_| { edit_effects(cx, |stack| stack.toggle_palette(selected), FrontendEvent::ToggleEffectPalette(selected)); },
+            }
+        }
+    })
+}
+
+// layers are listed top first, like the order they are composited in
+fn EffectStackControls(cx: Scope) -> Element {
+    let effects = use_read(&cx, EFFECTS);
+    let selected = *use_read(&cx, SELECTED_LAYER);
+    let set_selected = use_set(&cx, SELECTED_LAYER);
+    let count = effects.layers().len();
+
+    let rows = effects.layers().iter().enumerate().rev().map(|(i, layer)| {
+        let name = layer.settings.effect.name();
+        let class = if i == selected { "layer-row layer-selected" } else { "layer-row" };
+        let visibility = if layer.enabled { "On" } else { "Off" };
+        rsx!(
+            div {
+                key: "{i}",
+                class: "{class}",
+                div {
+                    class: "layer-name",
+                    onclick: move |_| set_selected(i),
+                    "{name}"
+                }
+                div {
+                    class: "layer-buttons",
+                    VoteButton {
+                        name: visibility,
+                        onclick: move |_| { edit_effects(cx, |stack| stack.toggle(i), FrontendEvent::ToggleEffect(i)); },
+                    }
+                    VoteButton {
+                        name: "^",
+                        onclick: move |_| if i + 1 < count { move_effect(cx, i, i + 1) },
+                    }
+                    VoteButton {
+                        name: "v",
+                        onclick: move |_| if i > 0 { move_effect(cx, i, i - 1) },
+                    }
+                    VoteButton {
+                        name: "x",
+                        onclick: move |_| remove_effect(cx, i),
+                    }
+                }
+            }
+        )
+    });
+
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "+",
+                onclick: move |_| add_effect(cx),
+            }
+        }
+        rows
+    })
+}
+
+fn HistoryControls(cx: Scope) -> Element {
+    let history = use_read(&cx, HISTORY);
+    let root = use_atom_root(&cx);
+    let next_undo = history.next_undo().map_or("-", |command| command.name());
+    let next_redo = history.next_redo().map_or("-", |command| command.name());
+
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            div {
+                class: "button-column",
+                VoteButton {
+                    name: "Undo",
+                    onclick: move |_| undo(root),
+                }
+                p {
+                    class: "history-step",
+                    "{next_undo}"
+                }
+            }
+            div {
+                class: "button-spacer"
+            }
+            div {
+                class: "button-column",
+                VoteButton {
+                    name: "Redo",
+                    onclick: move |_| redo(root),
+                }
+                p {
+                    class: "history-step",
+                    "{next_redo}"
+                }
+            }
+        }
+    })
+}
+
+fn Filmstrip(cx: Scope) -> Element {
+    let session = use_read(&cx, SESSION);
+    let active = *use_read(&cx, ACTIVE_IMAGE);
+
+    let thumbnails = session.iter().enumerate().map(|(i, image)| {
+        let class = if active == Some(i) { "thumbnail thumbnail-active" } else { "thumbnail" };
+        let name = &image.name;
+        let src = image.thumbnail.as_deref().unwrap_or_default();
+        rsx!(
+            div {
+                key: "{i}",
+                class: "{class}",
+                title: "{name}",
+                onclick: move |_| select_image(cx, i),
+                img {
+                    src: "{src}",
+                    alt: "{name}",
+                }
+            }
+        )
+    });
+
+    cx.render(rsx!{
+        div {
+            class: "filmstrip",
+            thumbnails
+        }
+    })
+}
+
+fn AdjustmentControls(cx: Scope) -> Element {
+    let adjustments = use_read(&cx, ADJUSTMENTS);
+    let lut = use_read(&cx, LUT);
+    let lut_error = use_read(&cx, LUT_ERROR);
+    let lut_title = lut.as_ref().map(|lut| lut.title.clone());
+    let export_lut = move |_| {
+        let baked = Lut::from_adjustments(adjustments, lut.as_ref());
+        download_bytes(baked.encode().as_bytes(), LUT_MIME, &baked.file_name());
+    };
+
+    cx.render(rsx!{
+        Slider {
+            name: "Exposure",
+            min: -3.0,
+            max: 3.0,
+            value: adjustments.exposure,
+            oninput: move |v| set_adjustment(cx, Adjustment::Exposure(v)),
+        }
+        Slider {
+            name: "Brightness",
+            min: -0.5,
+            max: 0.5,
+            value: adjustments.brightness,
+            oninput: move |v| set_adjustment(cx, Adjustment::Brightness(v)),
+        }
+        Slider {
+            name: "Contrast",
+            min: 0.0,
+            max: 2.0,
+            value: adjustments.contrast,
+            oninput: move |v| set_adjustment(cx, Adjustment::Contrast(v)),
+        }
+        Slider {
+            name: "Gamma",
+            min: 0.2,
+            max: 3.0,
+            value: adjustments.gamma,
+            oninput: move |v| set_adjustment(cx, Adjustment::Gamma(v)),
+        }
+        Slider {
+            name: "Saturation",
+            min: 0.0,
+            max: 2.0,
+            value: adjustments.saturation,
+            oninput: move |v| set_adjustment(cx, Adjustment::Saturation(v)),
+        }
+        div {
+            class: "button-row",
+            FileInput {
+                file_types: ".cube",
+                id: "lut-file",
+                label: "LUT",
+                oninput: move |_| import_lut(use_atom_root(&cx).clone(), get_files("lut-file")),
+            }
+        }
+        lut_error.as_ref().map(|e| rsx!(
+            p {
+                class: "error",
+                "{e}"
+            }
+        ))
+        lut_title.map(|title| rsx!(
+            Slider {
+                name: "LUT intensity",
+                min: 0.0,
+                max: 1.0,
+                value: adjustments.lut_intensity,
+                oninput: move |v| set_adjustment(cx, Adjustment::LutIntensity(v)),
+            }
+            div {
+                class: "button-row",
+                VoteButton {
+                    name: "Remove {title}",
+                    onclick: move |_| remove_lut(cx),
+                }
+            }
+        ))
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Export .cube",
+                onclick: export_lut,
+            }
+        }
+    })
+}
+
+// how near a point a press on the curve has to be to grab it, in levels
+const CURVE_GRAB_RADIUS: f32 = 0.05;
+
+// the levels under the pointer on the curve editor, `None` before it has rendered
+fn pointer_curve_point(event: &PointerData) -> Option<CurvePoint> {
+    let rect = element_rect("curve-editor").filter(|rect| rect.width > 0.0 && rect.height > 0.0)?;
+    Some(Curve::svg_point((event.client_x as f64 - rect.x) / rect.width, (event.client_y as f64 - rect.y) / rect.height))
+}
+
+fn CurvesPanel(cx: Scope) -> Element {
+    let root = use_atom_root(&cx);
+    // the point being dragged on the curve editor
+    let dragging = use_state(&cx, || None::<usize>);
+    let adjustments = use_read(&cx, ADJUSTMENTS);
+    let channel = *use_read(&cx, CURVE_CHANNEL);
+    let set_channel = use_set(&cx, CURVE_CHANNEL);
+    let channel_name = channel.name();
+    let levels = adjustments.curves.levels(channel);
+    let curve = *adjustments.curves.curve(channel);
+    let preview = histogram::svg_data_url(&curve.svg(channel.css()));
+    let straight = curve.is_identity();
+    let points = curve.points().iter().copied().enumerate().map(|(i, point)| {
+        let number = i + 1;
+        rsx!(
+            div {
+                key: "{i}",
+                class: "curve-point",
+                Slider {
+                    name: "Point {number} in",
+                    min: 0.0,
+                    max: 1.0,
+                    value: point.x,
+                    oninput: move |x| move_curve_point(cx, channel, i, CurvePoint { x, ..point }),
+                }
+                Slider {
+                    name: "Out",
+                    min: 0.0,
+                    max: 1.0,
+                    value: point.y,
+                    oninput: move |y| move_curve_point(cx, channel, i, CurvePoint { y, ..point }),
+                }
+                VoteButton {
+                    name: "x",
+                    onclick: move |_| edit_curve(cx, channel, |curve| curve.remove_point(i)),
+                }
+            }
+        )
+    });
+
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            VoteButton {
+                name: channel_name,
+                onclick: move |_| set_channel(channel.next()),
+            }
+        }
+        Slider {
+            name: "Black point",
+            min: 0.0,
+            max: 1.0,
+            value: levels.black,
+            oninput: move |black| set_adjustment(cx, Adjustment::Levels(channel, Levels { black, ..levels })),
+        }
+        Slider {
+            name: "White point",
+            min: 0.0,
+            max: 1.0,
+            value: levels.white,
+            oninput: move |white| set_adjustment(cx, Adjustment::Levels(channel, Levels { white, ..levels })),
+        }
+        Slider {
+            name: "Gamma",
+            min: 0.2,
+            max: 3.0,
+            value: levels.gamma,
+            oninput: move |gamma| set_adjustment(cx, Adjustment::Levels(channel, Levels { gamma, ..levels })),
+        }
+        div {
+            id: "curve-editor",
+            class: "curve-editor",
+            // grabs the point under the pointer, or puts a new one there
+            onpointerdown: move |evt: UiEvent<PointerData>| {
+                let at = match pointer_curve_point(&evt.data) {
+                    Some(at) => at,
+                    None => return,
                 };
+                let grabbed = curve.point_near(at, CURVE_GRAB_RADIUS);
+                let index = grabbed.or_else(|| {
+                    let mut added = None;
+                    edit_curve(cx, channel, |curve| added = curve.insert_point(at));
+                    added
+                });
+                // keeps the moves coming when the pointer leaves the editor
+                if let Some(editor) = get_optional_element_by_id("curve-editor") {
+                    let _ = editor.set_pointer_capture(evt.data.pointer_id);
+                }
+                dragging.set(index);
+            },
+            onpointermove: move |evt: UiEvent<PointerData>| {
+                if let (Some(index), Some(at)) = (**dragging, pointer_curve_point(&evt.data)) {
+                    move_curve_point(cx, channel, index, at);
+                }
+            },
+            onpointerup: move |_| {
+                if dragging.is_some() {
+                    dragging.set(None);
+                    end_merge(root);
+                }
+            },
+            onpointercancel: move |_| {
+                if dragging.is_some() {
+                    dragging.set(None);
+                    end_merge(root);
+                }
+            },
+            img {
+                class: "curve-preview",
+                draggable: "false",
+                src: "{preview}",
+            }
+        }
+        points
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Add point",
+                onclick: move |_| edit_curve(cx, channel, |curve| curve.add_point()),
             }
         }
+        (!straight).then(|| rsx!(
+            div {
+                class: "button-row",
+                VoteButton {
+                    name: "Reset curve",
+                    onclick: move |_| edit_curve(cx, channel, |curve| *curve = Curve::default()),
+                }
+            }
+        ))
     })
 }
 
-fn send_shader_event(cx: Scope, event: FrontendEvent) {
-    let proxy = use_read(&cx, PROXY);
-
-    if let Some(proxy) = proxy {
-        proxy.send_event(event);
-    }
-}
-
-fn send_img(proxy: &Option<EventLoopProxy<FrontendEvent>>, img: WebImage) {
-    if let Some(proxy) = proxy {
-        proxy.send_event(FrontendEvent::NewImage(img));
-    }
+// an empty field means "same as the source image"
+fn parse_dimension(value: &str) -> Option<u32> {
+    value.trim().parse::<u32>().ok().filter(|v| *v > 0)
 }
 
-static PROXY: Atom<Option<EventLoopProxy<FrontendEvent>>> = |_| None;
-static PIPELINE_STATUS: Atom<bool> = |_| false;
-static INITIAL_PAGE_LOAD: Atom<bool> = |_| false;
-static VALUE: Atom<i32> = |_| 0;
-
-static POSITION: Atom<(f64, f64)> = |_| (40f64, 40f64);
-
-static POSITIONS: Atom<HashMap<String, (f64, f64)>> = |_| HashMap::new();
-
-
-fn start(cx: Scope) {
-    let pipeline_status = use_read(&cx, PIPELINE_STATUS);
-    let set_pipeline_status = use_set(&cx, PIPELINE_STATUS);
-    let set_proxy = use_set(&cx, PROXY);
-    let initial_load = use_read(&cx, INITIAL_PAGE_LOAD);
-    let set_initial_load = use_set(&cx, INITIAL_PAGE_LOAD);
-
-
-    if !pipeline_status && *initial_load {
-        let (view_width, view_height) = dims("parent");
-        set_proxy(Some(run(view_width, view_height)));
-        set_pipeline_status(true);
-    } 
+// writes the preferences to storage whenever one of them changes
+fn PreferenceStore(cx: Scope) -> Element {
+    let preferences = Preferences {
+        panels: use_read(&cx, POSITIONS).clone(),
+        collapsed: use_read(&cx, COLLAPSED).clone(),
+        docked: use_read(&cx, DOCKED).clone(),
+        grid: *use_read(&cx, GRID),
+        color_index: *use_read(&cx, COLOR_INDEX),
+        palette_source: *use_read(&cx, PALETTE_SOURCE),
+        palettes: use_read(&cx, SAVED_PALETTES).clone(),
+        readable_colors: *use_read(&cx, READABLE_COLORS),
+        effects: use_read(&cx, EFFECTS).clone(),
+        adjustments: *use_read(&cx, ADJUSTMENTS),
+        fill: *use_read(&cx, FILL),
+    };
+    let last_stored = use_ref(&cx, String::new);
 
-    if !initial_load {
-        set_initial_load(true);
+    if let Some(json) = preferences::to_json(&preferences) {
+        if *last_stored.read() != json {
+            preferences::store(&json);
+            *last_stored.write_silent() = json;
+        }
     }
+    None
 }
 
-struct DraggableState {
-    id: String,
-    start_pos: (f64, f64),
-    pos: (f64, f64),
-}
-
-fn app(cx: Scope) -> Element {
-    //let mut value = use_state(&cx, || 0);
-    let value = use_read(&cx, VALUE);
-    let set_value = use_set(&cx, VALUE);
+// keeps the renderer's palette uniform on the entry the color panel shows
+fn PaletteSync(cx: Scope) -> Element {
     let proxy = use_read(&cx, PROXY);
+    let palette = active_palette(*use_read(&cx, PALETTE_SOURCE), use_read(&cx, SAVED_PALETTES), use_read(&cx, SESSION), *use_read(&cx, ACTIVE_IMAGE), *use_read(&cx, READABLE_COLORS));
+    let color = palette.get_color(*use_read(&cx, COLOR_INDEX)).clone();
+    let last_sent = use_ref(&cx, || None);
 
-    log::info!("updating ui");
-    start(cx);
+    // the renderer starts on the first built-in entry, so wait for it before marking anything sent
+    if let Some(proxy) = proxy {
+        if last_sent.read().as_ref() != Some(&color) {
+            let _ = proxy.send_event(FrontendEvent::SetPalette(color.clone()));
+            *last_sent.write_silent() = Some(color);
+        }
+    }
+    None
+}
 
-    let colors = use_state(&cx, || color_management::ColorList::new());
-    //let n = colors.make_mut().next_color();
-    let mut colors_index: &UseState<usize> = use_state(&cx, || 0);
-    let active_color = colors.get_color(*colors_index.current());
-    
+fn ProjectControls(cx: Scope) -> Element {
+    let embed = *use_read(&cx, EMBED_IMAGES);
+    let set_embed = use_set(&cx, EMBED_IMAGES);
+    let embed_name = if embed { "Embed images" } else { "Reference images" };
 
-    // Position on the square where drag was started
-    // If we are not currently dragging, `None`
-    let active_draggable: &UseState<Option<DraggableState>> = use_state(&cx, || None);
-    let positions: &UseState<HashMap<String, (f64, f64)>> = use_state(&cx, || HashMap::new());  
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            FileInput {
+                file_types: ".json,image/*",
+                id: "project",
+                label: "Open",
+                oninput: move |_| load_files(use_atom_root(&cx).clone(), get_files("project")),
+            }
+        }
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Save project",
+                onclick: move |_| save_project(cx),
+            }
+        }
+        div {
+            class: "button-row",
+            VoteButton {
+                name: embed_name,
+                onclick: move |_| set_embed(!embed),
+            }
+        }
+    })
+}
 
-    let mut draggables: Vec<DraggableState> = Vec::new();
+fn LayoutControls(cx: Scope) -> Element {
+    let grid = *use_read(&cx, GRID);
+    let set_grid = use_set(&cx, GRID);
+    let grid_name = match grid {
+        0 => String::from("No grid"),
+        size => format!("Grid {}px", size),
+    };
+    let next_grid = GRID_SIZES.iter()
+        .position(|&size| size == grid)
+        .map_or(GRID_SIZES[0], |i| GRID_SIZES[(i + 1) % GRID_SIZES.len()]);
+    // the layout is rebuilt from default positions for any panel missing here
+    let set_positions = use_set(&cx, POSITIONS);
+    let set_docked = use_set(&cx, DOCKED);
 
-    let (view_width, view_height) = dims("main");
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "{grid_name}",
+                onclick: move |_| set_grid(next_grid),
+            }
+        }
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Reset layout",
+                onclick: move |_| {
+                    set_positions(HashMap::new());
+                    set_docked(HashSet::new());
+                },
+            }
+        }
+    })
+}
 
-    let max = 10;
-    for i in 0..max {
-        let el = format!("el-{}", i);
+fn ExportControls(cx: Scope) -> Element {
+    let set_dialog = use_set(&cx, EXPORT_DIALOG);
 
-        if !positions.current().contains_key(&el) { // if key does not yet exist
-            positions.make_mut().insert(el.clone(), ((view_width / 2.0) + (100.0 * i as f64) - (30.0 * max as f64), view_height / 2.0 )); // set position to initial value
+    cx.render(rsx!{
+        div {
+            class: "button-row",
+            VoteButton {
+                name: "Save",
+                onclick: move |_| set_dialog(true),
+            }
         }
+    })
+}
 
-        let mut drag = DraggableState { // make new draggable
-            pos: *positions.current().get(&el).expect("missing position"),
-            start_pos: (0.0, 0.0),
-            id: el,
-        };
-
-        draggables.push(drag);
+fn ExportDialog(cx: Scope) -> Element {
+    let open = *use_read(&cx, EXPORT_DIALOG);
+    let set_dialog = use_set(&cx, EXPORT_DIALOG);
+    let options = use_read(&cx, EXPORT_OPTIONS);
+    let set_options = use_set(&cx, EXPORT_OPTIONS);
+    let session = use_read(&cx, SESSION);
+    let active = *use_read(&cx, ACTIVE_IMAGE);
+    let effects = use_read(&cx, EFFECTS);
+    let include_panels = *use_read(&cx, EXPORT_PANELS);
+    let set_include_panels = use_set(&cx, EXPORT_PANELS);
+    if !open {
+        return None;
     }
 
-    // When user starts dragging, track where on the square they started
-    let mouse_down_handler =
-        move |event: UiEvent<PointerData>, el: String| {
-            log::info!("mouse down!");
-            let pos = *positions.current().get(&el).expect("missing position");
-            active_draggable.set(Some(DraggableState {
-                start_pos: (event.data.page_x as f64 - pos.0, 
-                    event.data.page_y as f64 - pos.1),
-                pos: *positions.current().get(&el).expect("missing mousedown position"),
-                id: el,
-            }));
-            
-        };
-    
-    // When the mouse moves on the container
-    let mut mouse_move_handler = move |event: UiEvent<PointerData>| {
-        // If we are currently dragging the square
-        if let Some(active) = &**active_draggable {
-            // Calculate the new coordinates
-            // (Offset by the square coordinates we started dragging on, otherwise, we would drag the top-left corner)
-            let (s_x, s_y) = active.start_pos;
-            let x = (event.data.page_x as f64 - s_x as f64);
-            let y = (event.data.page_y as f64 - s_y as f64);
+    let effect_names: Vec<&str> = effects.layers().iter()
+        .filter(|layer| layer.enabled)
+        .map(|layer| layer.settings.effect.name())
+        .collect();
+    let source = active.and_then(|i| session.get(i));
+    let file_name = export::file_name(source.map(|image| image.name.as_str()), &effect_names, options.format);
+    let format_name = options.format.name();
+    let width = options.width.map(|w| w.to_string()).unwrap_or_default();
+    let height = options.height.map(|h| h.to_string()).unwrap_or_default();
+    let panels_name = if include_panels { "With panels" } else { "Image only" };
 
-            positions.make_mut().insert(active.id.clone(), (x, y));
-        }
-    };
-    
-    // When mouse is released, stop dragging
-    let mouse_up_handler = move |_: UiEvent<PointerData>| {
-        active_draggable.set(None);
+    let set_format = move |format: ExportFormat| set_options(ExportOptions { format, ..options.clone() });
+    let quality = match options.format {
+        ExportFormat::Jpeg(quality) => Some(quality),
+        _ => None,
     };
+    // nothing to keep or strip without source EXIF
+    let metadata = source.filter(|image| image.metadata.exif.is_some()).map(|image| {
+        let summary = image.metadata.summary().unwrap_or_else(|| String::from("EXIF"));
+        let action = match (options.keep_metadata, options.format.supports_metadata()) {
+            (true, true) => "Keep",
+            (true, false) => "Keep (not in TIFF)",
+            (false, _) => "Strip",
+        };
+        (summary, action)
+    });
 
-    let pos1 = draggables[0].pos;
-    let pos2 = draggables[1].pos;
-    let pos3 = draggables[2].pos;
-    let pos4 = draggables[3].pos;
-    let pos5 = draggables[4].pos;
-    let pos6 = draggables[5].pos;
-    let pos7 = draggables[6].pos;
-
-
-    for draggable in draggables {
-
-    }
-
-    cx.render(rsx!{ 
-        DecodeCanvas {}
-        DownloadAnchor {}
-        
-        div {
-            class: "row row-center",
-            Canvas {}
-        }  
+    cx.render(rsx!{
         div {
-            width: "100vw",
-            height: "100vh",
-            style: "overflow: hidden; height: 100vh; width: 100vw; position: absolute; top: 0; left: 0",
-            //u
-            onpointermove: mouse_move_handler,
-            onpointerup: mouse_up_handler,
-            Draggable {
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-0".to_string()),
-                pos: pos1,
-                div {
-                    class: "button-row",
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "+",
-                            onclick: move |_| colors_index += 1,
-                        }
-                    }
-                    div {
-                        class: "button-spacer"
-                    }
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "-",
-                            onclick: move |_| colors_index -= 1,
-                        }
-                    }
-                }
-                div {
-                    style: "display: table; width: 100%",
-                    h6 { 
-                        style: "display: table-cell; vertical-align: middle; width: 100%; text-align: center; height: 3.8rem; background-color: {active_color.accent}",
-                        "{colors_index}" 
-                    }
+            class: "export-dialog",
+            h6 {
+                class: "panel-title",
+                "Export {format_name}"
+            }
+            div {
+                class: "button-row export-formats",
+                VoteButton {
+                    name: "PNG",
+                    onclick: move |_| set_format(ExportFormat::Png),
                 }
-                div {
-                    class: "button-row",
-                    FileInput {
-                        file_types: "image",
-                        id: "img",
-                        oninput: move |_| prepare_img(cx, proxy),
-                    }
+                VoteButton {
+                    name: "JPEG",
+                    onclick: move |_| set_format(ExportFormat::Jpeg(ExportFormat::DEFAULT_JPEG_QUALITY)),
                 }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Effect",
-                        onclick: move |_| send_shader_event(cx, FrontendEvent::STEP),
-                    }
+                VoteButton {
+                    name: "WebP",
+                    onclick: move |_| set_format(ExportFormat::WebP),
                 }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Fill",
-                        onclick: move |_| send_shader_event(cx, FrontendEvent::FILL_MODE),
-                    }
+                VoteButton {
+                    name: "TIFF",
+                    onclick: move |_| set_format(ExportFormat::Tiff16),
                 }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Save",
-                        onclick: move |_| save_canvas(),
-                    }
-                } 
             }
-            Draggable { // light
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-1".to_string()),
-                pos: pos2,
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Light",
-                        onclick: move |_| (),
-                    }
-                }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Contrast",
-                        onclick: move |_| (),
-                    }
+            quality.map(|quality| rsx!(
+                Slider {
+                    name: "Quality",
+                    min: 1.0,
+                    max: 100.0,
+                    value: quality as f32,
+                    oninput: move |v: f32| set_format(ExportFormat::Jpeg(v.round() as u8)),
                 }
-
-            }
-            Draggable { // position
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-2".to_string()),
-                pos: pos3,
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Fill",
-                        onclick: move |_| (),
-                    }
+            ))
+            metadata.map(|(summary, action)| rsx!(
+                p {
+                    class: "export-metadata",
+                    "{summary}"
                 }
                 div {
                     class: "button-row",
                     VoteButton {
-                        name: "Fit",
-                        onclick: move |_| (),
+                        name: action,
+                        onclick: move |_| set_options(ExportOptions { keep_metadata: !options.keep_metadata, ..options.clone() }),
                     }
                 }
-                div {
-                    class: "button-row",
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "&#xbf;",
-                            onclick: move |_| set_value(value + 1),
-                        }
-                    }
-                    div {
-                        class: "button-spacer"
-                    }
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "?",
-                            onclick: move |_| set_value(value - 1),
-                        }
-                    }
+            ))
+            div {
+                class: "button-row",
+                VoteButton {
+                    name: panels_name,
+                    onclick: move |_| set_include_panels(!include_panels),
                 }
-                
             }
-            Draggable { // layers
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-3".to_string()),
-                pos: pos4,
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "+",
-                        onclick: move |_| (),
-                    }
-                }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Two",
-                        onclick: move |_| (),
-                    }
+            div {
+                class: "button-row export-size",
+                input {
+                    r#type: "number",
+                    min: "1",
+                    placeholder: "W",
+                    value: "{width}",
+                    oninput: move |evt: FormEvent| set_options(ExportOptions { width: parse_dimension(&evt.value), ..options.clone() }),
                 }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "One",
-                        onclick: move |_| (),
-                    }
+                input {
+                    r#type: "number",
+                    min: "1",
+                    placeholder: "H",
+                    value: "{height}",
+                    oninput: move |evt: FormEvent| set_options(ExportOptions { height: parse_dimension(&evt.value), ..options.clone() }),
                 }
             }
-            Draggable { //color
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-4".to_string()),
-                pos: pos5,
-                div {
-                    class: "button-row",
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "<",
-                            onclick: move |_| set_value(value + 1),
-                        }
-                    }
-                    div {
-                        class: "button-spacer"
-                    }
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: ">",
-                            onclick: move |_| set_value(value - 1),
-                        }
-                    }
-                }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Invert",
-                        onclick: move |_| (),
-                    }
-                }
+            p {
+                class: "export-name",
+                "{file_name}"
             }
-            Draggable { //color
-                onpointerdown: move |evt| mouse_down_handler(evt, "el-5".to_string()),
-                pos: pos6,
-                div {
-                    class: "button-row",
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "^",
-                            onclick: move |_| set_value(value + 1),
-                        }
-                    }
-                    div {
-                        class: "button-spacer"
-                    }
-                    div {
-                        class: "button-column",
-                        VoteButton {
-                            name: "v",
-                            onclick: move |_| set_value(value - 1),
-                        }
-                    }
-                }
-                div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Invert",
-                        onclick: move |_| (),
-                    }
-                }
+            div {
+                class: "button-row",
                 div {
-                    class: "button-row",
+                    class: "button-column",
                     VoteButton {
-                        name: "Invert",
-                        onclick: move |_| (),
+                        name: "Export",
+                        onclick: move |_| {
+                            let overlay = if include_panels { capture_overlay() } else { None };
+                            send_shader_event(cx, FrontendEvent::Export(ExportOptions { file_name: file_name.clone(), overlay, ..options.clone() }));
+                            set_dialog(false);
+                        },
                     }
                 }
                 div {
-                    class: "button-row",
-                    VoteButton {
-                        name: "Invert",
-                        onclick: move |_| (),
-                    }
+                    class: "button-spacer"
                 }
                 div {
-                    class: "button-row",
+                    class: "button-column",
                     VoteButton {
-                        name: "Invert",
-                        onclick: move |_| (),
+                        name: "Cancel",
+                        onclick: move |_| set_dialog(false),
                     }
                 }
             }
@@ -434,28 +2094,6 @@ fn app(cx: Scope) -> Element {
     })
 }
 
-//borrowed props (no partialeq)
-#[derive(Props)] 
-struct VoteButtonProps<'a> {
-    name: &'a str,
-    onclick: EventHandler<'a, MouseEvent>
-}
-
-fn VoteButton<'a>(cx: Scope<'a,VoteButtonProps<'a>>) -> Element {
-    cx.render(rsx!{
-        div {
-            button {
-                class: "button button-outline",
-                width: "100%",
-                onclick: move |evt| cx.props.onclick.call(evt),
-                "{cx.props.name}"
-            }
-        }
-    })
-}
-
-
-
 fn Canvas(cx: Scope) -> Element {
     cx.render(rsx!{
         div {
@@ -494,6 +2132,8 @@ fn DownloadAnchor(cx: Scope) -> Element {
 struct FileInputProps<'a> {
     file_types: &'a str,
     id: &'a str,
+    #[props(default = "Image")]
+    label: &'a str,
     oninput: EventHandler<'a, FormEvent>
 }
 
@@ -505,13 +2145,14 @@ fn FileInput<'a>(cx: Scope<'a, FileInputProps<'a>>) -> Element {
                 r#for: format_args!("{}", cx.props.id),
                 class: "button button-solid",
                 width: "100%",
-                "Image"
+                "{cx.props.label}"
             }
             input {
                 r#type: "file",
                 id: format_args!("{}", cx.props.id),
                 name: format_args!("{}", cx.props.id),
                 accept: format_args!("{}", cx.props.file_types),
+                multiple: "true",
                 oninput: move |evt| cx.props.oninput.call(evt)
             }
         }
@@ -521,20 +2162,50 @@ fn FileInput<'a>(cx: Scope<'a, FileInputProps<'a>>) -> Element {
 #[derive(Props)] 
 struct DraggableProps<'a> {
     onpointerdown: EventHandler<'a, UiEvent<PointerData>>,
+    ontoggle: EventHandler<'a, MouseEvent>,
+    ondock: EventHandler<'a, MouseEvent>,
+    id: &'a str,
     pos: (f64, f64),
+    title: &'a str,
+    collapsed: bool,
+    docked: bool,
     children: Element<'a>
 }
 
 fn Draggable<'a>(cx: Scope<'a, DraggableProps<'a>>) -> Element {
+    let toggle = if cx.props.collapsed { "+" } else { "-" };
+    let dock = if cx.props.docked { "«" } else { "»" };
+    let class = if cx.props.docked { "draggable docked" } else { "draggable" };
+    let element_id = panel_element_id(cx.props.id);
     cx.render(rsx!{
         div {
-            class: "draggable",
+            id: "{element_id}",
+            class: "{class}",
             left: "{cx.props.pos.0}px",
             top: "{cx.props.pos.1}px",
             DragIcon {
                 onpointerdown:  move |evt| cx.props.onpointerdown.call(evt),
             }
-            &cx.props.children,
+            div {
+                class: "panel-header",
+                h6 {
+                    class: "panel-title",
+                    "{cx.props.title}"
+                }
+                button {
+                    class: "panel-toggle",
+                    onclick: move |evt| cx.props.ondock.call(evt),
+                    "{dock}"
+                }
+                button {
+                    class: "panel-toggle",
+                    onclick: move |evt| cx.props.ontoggle.call(evt),
+                    "{toggle}"
+                }
+            }
+            (!cx.props.collapsed).then(|| rsx!(
+                &cx.props.children
+            ))
         }
     })
 }
@@ -0,0 +1,364 @@
+use std::io::Cursor;
+
+use exif::experimental::Writer;
+use exif::{Exif, In, Tag, Value};
+use image::{imageops, RgbaImage};
+
+use crate::export::ExportFormat;
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// EXIF details read from the source file. `exif` is the raw TIFF-structured
+/// block so it can be written back into an export untouched.
+/// For a TIFF source it's rebuilt with just the tags, see `tags_only`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageMetadata {
+    /// EXIF orientation 1-8, 0 when the file has none.
+    pub orientation: u16,
+    pub camera: Option<String>,
+    pub capture_date: Option<String>,
+    pub color_space: Option<String>,
+    pub exif: Option<Vec<u8>>,
+}
+
+impl ImageMetadata {
+    /// Missing or unreadable EXIF isn't an error, the image just has no metadata.
+    pub fn read(bytes: &[u8]) -> ImageMetadata {
+        let exif = match exif::Reader::new().read_from_container(&mut Cursor::new(bytes)) {
+            Ok(exif) => exif,
+            Err(_) => return ImageMetadata::default(),
+        };
+        let text = |tag| exif.get_field(tag, In::PRIMARY).and_then(|field| match &field.value {
+            Value::Ascii(values) => values.first()
+                .map(|v| String::from_utf8_lossy(v).trim_end_matches(['\0', ' ']).to_string())
+                .filter(|v| !v.is_empty()),
+            _ => None,
+        });
+
+        let camera = match (text(Tag::Make), text(Tag::Model)) {
+            // most models already start with the make, "Canon Canon EOS" reads badly
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+
+        ImageMetadata {
+            orientation: exif.get_field(Tag::Orientation, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+                .filter(|v| (1..=8).contains(v))
+                .map_or(0, |v| v as u16),
+            camera,
+            capture_date: text(Tag::DateTimeOriginal).or_else(|| text(Tag::DateTime)),
+            color_space: exif.get_field(Tag::ColorSpace, In::PRIMARY)
+                .map(|field| field.display_value().to_string()),
+            exif: if is_tiff(bytes) { tags_only(&exif) } else { Some(exif.buf().to_vec()) },
+        }
+    }
+
+    /// One line for the UI, e.g. "Pixel 7, 2023:05:01 12:00:00, sRGB".
+    pub fn summary(&self) -> Option<String> {
+        let parts: Vec<&str> = [&self.camera, &self.capture_date, &self.color_space]
+            .into_iter()
+            .filter_map(|part| part.as_deref())
+            .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+}
+
+/// Rotates and mirrors `img` so it displays upright, per the EXIF orientation.
+pub fn apply_orientation(img: RgbaImage, orientation: u16) -> RgbaImage {
+    match orientation {
+        2 => imageops::flip_horizontal(&img),
+        3 => imageops::rotate180(&img),
+        4 => imageops::flip_vertical(&img),
+        5 => imageops::flip_horizontal(&imageops::rotate90(&img)),
+        6 => imageops::rotate90(&img),
+        7 => imageops::flip_horizontal(&imageops::rotate270(&img)),
+        8 => imageops::rotate270(&img),
+        _ => img,
+    }
+}
+
+/// Writes `exif` into an already encoded file. The export is rendered
+/// upright, so the orientation tag is reset to 1 first. TIFF output would
+/// need the tags merged into its own IFD and is left as is.
+pub fn embed_exif(encoded: Vec<u8>, format: ExportFormat, exif: &[u8]) -> Vec<u8> {
+    let mut exif = exif.to_vec();
+    reset_orientation(&mut exif);
+
+    let embedded = match format {
+        ExportFormat::Jpeg(_) => embed_jpeg(&encoded, &exif),
+        ExportFormat::Png => embed_png(&encoded, &exif),
+        ExportFormat::WebP => embed_webp(&encoded, &exif),
+        ExportFormat::Tiff16 => None,
+    };
+    embedded.unwrap_or_else(|| {
+        log::warn!("couldn't embed metadata in the {} export", format.name());
+        encoded
+    })
+}
+
+fn is_tiff(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*")
+}
+
+// A TIFF's EXIF block is the whole file, pixels included. Writes the primary
+// image's tags into a fresh block instead, leaving out the ones describing
+// the TIFF's own pixel layout, the writer drops the strip and tile offsets.
+fn tags_only(exif: &Exif) -> Option<Vec<u8>> {
+    let mut writer = Writer::new();
+    for field in exif.fields().filter(|field| field.ifd_num == In::PRIMARY) {
+        if !matches!(field.tag, Tag::ImageWidth | Tag::ImageLength | Tag::BitsPerSample
+            | Tag::Compression | Tag::PhotometricInterpretation | Tag::SamplesPerPixel
+            | Tag::RowsPerStrip | Tag::PlanarConfiguration)
+        {
+            writer.push_field(field);
+        }
+    }
+    let mut out = Cursor::new(Vec::new());
+    writer.write(&mut out, exif.little_endian()).ok()?;
+    Some(out.into_inner())
+}
+
+// overwrites the IFD0 orientation value in place, leaving every offset valid
+fn reset_orientation(tiff: &mut [u8]) {
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return,
+    };
+    let read_u16 = |b: &[u8], at: usize| -> Option<u16> {
+        let bytes = [*b.get(at)?, *b.get(at + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read_u32 = |b: &[u8], at: usize| -> Option<u32> {
+        let bytes = [*b.get(at)?, *b.get(at + 1)?, *b.get(at + 2)?, *b.get(at + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+
+    let ifd = match read_u32(tiff, 4) {
+        Some(offset) => offset as usize,
+        None => return,
+    };
+    let count = read_u16(tiff, ifd).unwrap_or(0) as usize;
+    for entry in (0..count).map(|i| ifd + 2 + i * 12) {
+        if read_u16(tiff, entry) == Some(ORIENTATION_TAG) {
+            // SHORT values sit left-aligned in the 4 byte value field
+            let one = if little_endian { 1u16.to_le_bytes() } else { 1u16.to_be_bytes() };
+            if let Some(value) = tiff.get_mut(entry + 8..entry + 10) {
+                value.copy_from_slice(&one);
+            }
+            return;
+        }
+    }
+}
+
+// APP1 segment straight after SOI
+fn embed_jpeg(jpeg: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let length = u16::try_from(2 + 6 + exif.len()).ok()?;
+
+    let mut out = Vec::with_capacity(jpeg.len() + exif.len() + 10);
+    out.extend_from_slice(&[0xff, 0xd8, 0xff, 0xe1]);
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(b"Exif\0\0");
+    out.extend_from_slice(exif);
+    out.extend_from_slice(&jpeg[2..]);
+    Some(out)
+}
+
+// eXIf chunk straight after IHDR
+fn embed_png(png: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        return None;
+    }
+    let length = u32::try_from(exif.len()).ok()?;
+
+    let mut chunk = Vec::with_capacity(exif.len() + 12);
+    chunk.extend_from_slice(&length.to_be_bytes());
+    chunk.extend_from_slice(b"eXIf");
+    chunk.extend_from_slice(exif);
+    let crc = crc32fast::hash(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+
+    let mut out = Vec::with_capacity(png.len() + chunk.len());
+    out.extend_from_slice(&png[..IHDR_END]);
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&png[IHDR_END..]);
+    Some(out)
+}
+
+// a simple VP8L file becomes the extended format: VP8X header, image, EXIF chunk
+fn embed_webp(webp: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+    if webp.len() < 30 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" || &webp[12..16] != b"VP8L" {
+        return None;
+    }
+    // VP8L header: signature byte, then 14 bit width-1 and height-1
+    let bits = u32::from_le_bytes([webp[21], webp[22], webp[23], webp[24]]);
+    let width_minus_one = bits & 0x3fff;
+    let height_minus_one = (bits >> 14) & 0x3fff;
+    let has_alpha = (bits >> 28) & 1 == 1;
+
+    let mut out = Vec::with_capacity(webp.len() + exif.len() + 32);
+    out.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+
+    out.extend_from_slice(b"VP8X");
+    out.extend_from_slice(&10u32.to_le_bytes());
+    out.push(0x08 | if has_alpha { 0x10 } else { 0 });
+    out.extend_from_slice(&[0; 3]);
+    out.extend_from_slice(&width_minus_one.to_le_bytes()[..3]);
+    out.extend_from_slice(&height_minus_one.to_le_bytes()[..3]);
+
+    out.extend_from_slice(&webp[12..]);
+
+    out.extend_from_slice(b"EXIF");
+    out.extend_from_slice(&u32::try_from(exif.len()).ok()?.to_le_bytes());
+    out.extend_from_slice(exif);
+    if exif.len() % 2 == 1 {
+        out.push(0);
+    }
+
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Field;
+    use image::{ImageOutputFormat, Rgba};
+
+    // 1x1 lossless webp, the image crate can't encode webp itself
+    const WEBP_LOSSLESS: &[u8] = &[
+        0x52, 0x49, 0x46, 0x46, 0x1a, 0x00, 0x00, 0x00, 0x57, 0x45, 0x42, 0x50,
+        0x56, 0x50, 0x38, 0x4c, 0x0d, 0x00, 0x00, 0x00, 0x2f, 0x00, 0x00, 0x00,
+        0x10, 0x07, 0x10, 0x11, 0x11, 0x88, 0x88, 0xfe, 0x07, 0x00,
+    ];
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 100, y as u8 * 200, 50, 255]))
+    }
+
+    fn encode(format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(sample()).write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value }
+    }
+
+    fn exif_block(little_endian: bool) -> Vec<u8> {
+        let orientation = field(Tag::Orientation, Value::Short(vec![6]));
+        let make = field(Tag::Make, Value::Ascii(vec![b"Pixel".to_vec()]));
+        let mut writer = Writer::new();
+        writer.push_field(&orientation);
+        writer.push_field(&make);
+        let mut out = Cursor::new(Vec::new());
+        writer.write(&mut out, little_endian).unwrap();
+        out.into_inner()
+    }
+
+    fn orientation_of(tiff: Vec<u8>) -> Option<u32> {
+        exif::Reader::new().read_raw(tiff).ok()?
+            .get_field(Tag::Orientation, In::PRIMARY)?
+            .value.get_uint(0)
+    }
+
+    #[test]
+    fn orients_every_exif_value() {
+        let (top_left, top_right) = (*sample().get_pixel(0, 0), *sample().get_pixel(2, 0));
+        // where the stored top corners end up, on a 3x2 or, turned, 2x3 image
+        let expected = [
+            (1, (3, 2), (0, 0), (2, 0)),
+            (2, (3, 2), (2, 0), (0, 0)),
+            (3, (3, 2), (2, 1), (0, 1)),
+            (4, (3, 2), (0, 1), (2, 1)),
+            (5, (2, 3), (0, 0), (0, 2)),
+            (6, (2, 3), (1, 0), (1, 2)),
+            (7, (2, 3), (1, 2), (1, 0)),
+            (8, (2, 3), (0, 2), (0, 0)),
+        ];
+        for (orientation, size, left, right) in expected {
+            let img = apply_orientation(sample(), orientation);
+            assert_eq!(img.dimensions(), size, "orientation {}", orientation);
+            assert_eq!(*img.get_pixel(left.0, left.1), top_left, "orientation {}", orientation);
+            assert_eq!(*img.get_pixel(right.0, right.1), top_right, "orientation {}", orientation);
+        }
+    }
+
+    #[test]
+    fn resets_orientation_in_either_byte_order() {
+        for little_endian in [true, false] {
+            let mut tiff = exif_block(little_endian);
+            let before = tiff.len();
+            reset_orientation(&mut tiff);
+            assert_eq!(tiff.len(), before);
+            assert_eq!(orientation_of(tiff), Some(1), "little endian: {}", little_endian);
+        }
+    }
+
+    #[test]
+    fn embedded_exif_reads_back_upright() {
+        let exif = exif_block(true);
+        let cases = [
+            (ExportFormat::Jpeg(90), encode(ImageOutputFormat::Jpeg(90))),
+            (ExportFormat::Png, encode(ImageOutputFormat::Png)),
+            (ExportFormat::WebP, WEBP_LOSSLESS.to_vec()),
+        ];
+        for (format, encoded) in cases {
+            let embedded = embed_exif(encoded.clone(), format, &exif);
+            assert_ne!(embedded, encoded, "{}", format.name());
+            let metadata = ImageMetadata::read(&embedded);
+            assert_eq!(metadata.orientation, 1, "{}", format.name());
+            assert_eq!(metadata.camera.as_deref(), Some("Pixel"), "{}", format.name());
+            assert!(image::load_from_memory(&embedded).is_ok(), "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn leaves_unknown_files_alone() {
+        let exif = exif_block(true);
+        assert_eq!(embed_jpeg(b"not a jpeg", &exif), None);
+        assert_eq!(embed_png(b"not a png", &exif), None);
+        assert_eq!(embed_webp(b"not a webp", &exif), None);
+    }
+
+    #[test]
+    fn keeps_tiff_pixels_out_of_the_exif() {
+        let pixels = vec![0xab; 4096];
+        let fields = [
+            field(Tag::ImageWidth, Value::Long(vec![64])),
+            field(Tag::ImageLength, Value::Long(vec![64])),
+            field(Tag::PhotometricInterpretation, Value::Short(vec![1])),
+            field(Tag::Orientation, Value::Short(vec![6])),
+            field(Tag::Make, Value::Ascii(vec![b"Pixel".to_vec()])),
+        ];
+        let strips: [&[u8]; 1] = [&pixels];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        writer.set_strips(&strips, In::PRIMARY);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, true).unwrap();
+
+        let metadata = ImageMetadata::read(&tiff.into_inner());
+        assert_eq!(metadata.orientation, 6);
+        let exif = metadata.exif.unwrap();
+        assert!(exif.len() < pixels.len());
+        assert_eq!(orientation_of(exif.clone()), Some(6));
+        let read = exif::Reader::new().read_raw(exif).unwrap();
+        assert!(read.get_field(Tag::StripOffsets, In::PRIMARY).is_none());
+        assert!(read.get_field(Tag::ImageWidth, In::PRIMARY).is_none());
+    }
+}
@@ -0,0 +1,315 @@
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, CssStyleDeclaration, DomRect, Element, HtmlCanvasElement, HtmlImageElement, HtmlInputElement, ImageData, Node, Range};
+
+use crate::layout::Rect;
+use crate::render_pipeline::WebImage;
+
+// height of a range input's track, in CSS pixels like the rest of the panel
+const TRACK_HEIGHT: f64 = 4.0;
+
+/// The panels as they are on screen, drawn over an export so it matches
+/// the page. Rects are in CSS pixels relative to the canvas.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    /// On-screen size of the canvas the rects are relative to.
+    pub view: (f64, f64),
+    /// Everything in the panels, in the order the page paints it.
+    pub items: Vec<Item>,
+}
+
+/// One thing a panel draws, read off the page with its computed style.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    /// An element's background, border and outline, whichever it has.
+    Block {
+        rect: Rect,
+        radius: f64,
+        fill: Option<String>,
+        border: Option<Stroke>,
+        outline: Option<Stroke>,
+    },
+    Text {
+        rect: Rect,
+        text: String,
+        font: Font,
+        color: String,
+    },
+    /// Histograms, curves and thumbnails, drawn from the loaded element.
+    Image {
+        rect: Rect,
+        image: HtmlImageElement,
+    },
+    /// A range input, `value` 0-1 along its track.
+    Slider {
+        rect: Rect,
+        value: f64,
+        color: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stroke {
+    pub width: f64,
+    pub color: String,
+}
+
+/// A computed font, kept in parts so the size can be scaled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Font {
+    pub style: String,
+    pub weight: String,
+    pub size: f64,
+    pub family: String,
+}
+
+impl Font {
+    fn css(&self, scale: f64) -> String {
+        format!("{} {} {}px {}", self.style, self.weight, self.size * scale, self.family)
+    }
+}
+
+impl Overlay {
+    /// Reads `panels` off the page, relative to `canvas`.
+    pub fn capture(canvas: &Element, panels: &[Element]) -> Option<Overlay> {
+        let window = web_sys::window()?;
+        let range = window.document()?.create_range().ok()?;
+        let view = canvas.get_bounding_client_rect();
+        let origin = (view.x(), view.y());
+
+        let mut items = Vec::new();
+        for panel in panels {
+            capture_element(&window, &range, origin, panel, &mut items);
+        }
+        Some(Overlay { view: (view.width(), view.height()), items })
+    }
+
+    /// Export size when no size is chosen: the canvas's aspect ratio, with
+    /// the long side as long as the image's so nothing is downsampled.
+    pub fn frame_size(&self, image: (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.view;
+        if width <= 0.0 || height <= 0.0 {
+            return image;
+        }
+        let scale = image.0.max(image.1) as f64 / width.max(height);
+        (((width * scale).round() as u32).max(1), ((height * scale).round() as u32).max(1))
+    }
+
+    /// Draws the panels into `img`, scaled from the canvas to the export size.
+    /// Goes through a 2d canvas, so text is set in the fonts the page uses.
+    pub fn composite(&self, img: &mut WebImage) -> Result<(), String> {
+        let document = web_sys::window()
+            .and_then(|win| win.document())
+            .ok_or("no document")?;
+        let canvas = document.create_element("canvas")
+            .ok()
+            .and_then(|el| el.dyn_into::<HtmlCanvasElement>().ok())
+            .ok_or("couldn't create a canvas")?;
+        canvas.set_width(img.width);
+        canvas.set_height(img.height);
+        let ctx = canvas.get_context("2d")
+            .ok()
+            .flatten()
+            .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+            .ok_or("no 2d context")?;
+
+        let pixels = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&img.data), img.width, img.height)
+            .map_err(|e| format!("{:?}", e))?;
+        ctx.put_image_data(&pixels, 0.0, 0.0).map_err(|e| format!("{:?}", e))?;
+
+        // a chosen export size can stretch the frame, text and lines follow the smaller side
+        let (scale_x, scale_y) = (img.width as f64 / self.view.0, img.height as f64 / self.view.1);
+        let scale = scale_x.min(scale_y);
+        let scaled = |rect: &Rect| Rect {
+            x: rect.x * scale_x,
+            y: rect.y * scale_y,
+            width: rect.width * scale_x,
+            height: rect.height * scale_y,
+        };
+
+        ctx.set_text_align("left");
+        ctx.set_text_baseline("middle");
+        for item in &self.items {
+            match item {
+                Item::Block { rect, radius, fill, border, outline } => {
+                    let rect = scaled(rect);
+                    if let Some(fill) = fill {
+                        rounded_rect(&ctx, &rect, radius * scale);
+                        ctx.set_fill_style(&fill.as_str().into());
+                        ctx.fill();
+                    }
+                    // borders sit inside the box, outlines around it
+                    if let Some(border) = border {
+                        let inset = border.width * scale / 2.0;
+                        stroke(&ctx, &inset_rect(&rect, inset), (radius * scale - inset).max(0.0), border, scale);
+                    }
+                    if let Some(outline) = outline {
+                        let outset = outline.width * scale / 2.0;
+                        stroke(&ctx, &inset_rect(&rect, -outset), radius * scale + outset, outline, scale);
+                    }
+                }
+                Item::Text { rect, text, font, color } => {
+                    let rect = scaled(rect);
+                    ctx.set_font(&font.css(scale));
+                    ctx.set_fill_style(&color.as_str().into());
+                    let _ = ctx.fill_text_with_max_width(text, rect.x, rect.y + rect.height / 2.0, rect.width.max(1.0));
+                }
+                Item::Image { rect, image } => {
+                    // still loading, or a broken source
+                    if !image.complete() || image.natural_width() == 0 {
+                        continue;
+                    }
+                    let rect = scaled(rect);
+                    ctx.draw_image_with_html_image_element_and_dw_and_dh(image, rect.x, rect.y, rect.width, rect.height)
+                        .map_err(|e| format!("{:?}", e))?;
+                }
+                Item::Slider { rect, value, color } => {
+                    let rect = scaled(rect);
+                    let track = TRACK_HEIGHT * scale;
+                    let middle = rect.y + rect.height / 2.0;
+                    ctx.set_fill_style(&color.as_str().into());
+                    ctx.set_global_alpha(0.4);
+                    rounded_rect(&ctx, &Rect { y: middle - track / 2.0, height: track, ..rect }, track / 2.0);
+                    ctx.fill();
+                    ctx.set_global_alpha(1.0);
+
+                    let thumb = rect.height.min(rect.width) / 2.0;
+                    let x = rect.x + thumb + value * (rect.width - 2.0 * thumb);
+                    ctx.begin_path();
+                    let _ = ctx.arc(x, middle, thumb, 0.0, std::f64::consts::TAU);
+                    ctx.fill();
+                }
+            }
+        }
+
+        img.data = ctx.get_image_data(0.0, 0.0, img.width as f64, img.height as f64)
+            .map_err(|e| format!("{:?}", e))?
+            .data()
+            .to_vec();
+        Ok(())
+    }
+}
+
+// walks `el` and its children in paint order, what's hidden is left out
+fn capture_element(window: &web_sys::Window, range: &Range, origin: (f64, f64), el: &Element, items: &mut Vec<Item>) {
+    let style = match window.get_computed_style(el) {
+        Ok(Some(style)) => style,
+        _ => return,
+    };
+    let property = |name: &str| style.get_property_value(name).unwrap_or_default();
+    if property("display") == "none" || property("visibility") == "hidden" {
+        return;
+    }
+    let rect = relative(&el.get_bounding_client_rect(), origin);
+
+    if let Some(image) = el.dyn_ref::<HtmlImageElement>() {
+        items.push(Item::Image { rect, image: image.clone() });
+        return;
+    }
+    if let Some(input) = el.dyn_ref::<HtmlInputElement>() {
+        capture_input(input, &style, rect, items);
+        return;
+    }
+
+    let fill = Some(property("background-color")).filter(|color| visible(color));
+    let border = line(&style, "border-top");
+    let outline = line(&style, "outline");
+    if fill.is_some() || border.is_some() || outline.is_some() {
+        let radius = px(&property("border-top-left-radius"));
+        items.push(Item::Block { rect, radius, fill, border, outline });
+    }
+
+    let mut child = el.first_child();
+    while let Some(node) = child {
+        match node.node_type() {
+            Node::ELEMENT_NODE => {
+                if let Some(child) = node.dyn_ref::<Element>() {
+                    capture_element(window, range, origin, child, items);
+                }
+            }
+            Node::TEXT_NODE => {
+                let text = node.text_content().unwrap_or_default();
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() && range.select_node_contents(&node).is_ok() {
+                    let rect = relative(&range.get_bounding_client_rect(), origin);
+                    items.push(Item::Text { rect, text, font: font(&style), color: property("color") });
+                }
+            }
+            _ => {}
+        }
+        child = node.next_sibling();
+    }
+}
+
+fn capture_input(input: &HtmlInputElement, style: &CssStyleDeclaration, rect: Rect, items: &mut Vec<Item>) {
+    let color = style.get_property_value("color").unwrap_or_default();
+    match input.type_().as_str() {
+        "range" => {
+            let bound = |value: String, default: f64| value.parse().unwrap_or(default);
+            let (min, max) = (bound(input.min(), 0.0), bound(input.max(), 100.0));
+            let value = if max > min { ((input.value_as_number() - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 };
+            items.push(Item::Slider { rect, value, color });
+        }
+        "color" => {
+            items.push(Item::Block { rect, radius: 0.0, fill: Some(input.value()), border: None, outline: None });
+        }
+        _ => {
+            if !input.value().is_empty() {
+                items.push(Item::Text { rect, text: input.value(), font: font(style), color });
+            }
+        }
+    }
+}
+
+fn font(style: &CssStyleDeclaration) -> Font {
+    let property = |name: &str| style.get_property_value(name).unwrap_or_default();
+    Font {
+        style: property("font-style"),
+        weight: property("font-weight"),
+        size: px(&property("font-size")),
+        family: property("font-family"),
+    }
+}
+
+// a border side or outline, `None` when it isn't drawn
+fn line(style: &CssStyleDeclaration, prefix: &str) -> Option<Stroke> {
+    let property = |part: &str| style.get_property_value(&format!("{}-{}", prefix, part)).unwrap_or_default();
+    let width = px(&property("width"));
+    let color = property("color");
+    (property("style") != "none" && width > 0.0 && visible(&color)).then_some(Stroke { width, color })
+}
+
+fn relative(rect: &DomRect, origin: (f64, f64)) -> Rect {
+    Rect { x: rect.x() - origin.0, y: rect.y() - origin.1, width: rect.width(), height: rect.height() }
+}
+
+// computed colors come back as rgb() or rgba(), fully transparent is skipped
+fn visible(color: &str) -> bool {
+    !color.is_empty() && color != "transparent" && !color.replace(' ', "").ends_with(",0)")
+}
+
+fn px(value: &str) -> f64 {
+    value.trim().trim_end_matches("px").parse().unwrap_or(0.0)
+}
+
+fn inset_rect(rect: &Rect, by: f64) -> Rect {
+    Rect { x: rect.x + by, y: rect.y + by, width: rect.width - 2.0 * by, height: rect.height - 2.0 * by }
+}
+
+fn stroke(ctx: &CanvasRenderingContext2d, rect: &Rect, radius: f64, stroke: &Stroke, scale: f64) {
+    rounded_rect(ctx, rect, radius);
+    ctx.set_line_width(stroke.width * scale);
+    ctx.set_stroke_style(&stroke.color.as_str().into());
+    ctx.stroke();
+}
+
+fn rounded_rect(ctx: &CanvasRenderingContext2d, rect: &Rect, radius: f64) {
+    let radius = radius.min(rect.width / 2.0).min(rect.height / 2.0).max(0.0);
+    let (left, top, right, bottom) = (rect.x, rect.y, rect.x + rect.width, rect.y + rect.height);
+    ctx.begin_path();
+    ctx.move_to(left + radius, top);
+    let _ = ctx.arc_to(right, top, right, bottom, radius);
+    let _ = ctx.arc_to(right, bottom, left, bottom, radius);
+    let _ = ctx.arc_to(left, bottom, left, top, radius);
+    let _ = ctx.arc_to(left, top, right, top, radius);
+    ctx.close_path();
+}
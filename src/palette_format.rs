@@ -0,0 +1,413 @@
+use hex_color::HexColor;
+use serde::{Deserialize, Serialize};
+
+use crate::color_management::{ColorEntry, ColorList};
+
+// names given to the colors of each entry in formats that only hold a flat
+// list, so the entries come back together on import
+const ROLES: [&str; 3] = ["background", "foreground", "accent"];
+
+// colors read from a file, with the name each was given there
+type Swatches = Vec<(Option<String>, HexColor)>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaletteFormat {
+    /// Entries with their roles, the format palettes are stored in.
+    Json,
+    /// GIMP and Inkscape palette.
+    Gpl,
+    /// Adobe Swatch Exchange.
+    Ase,
+    /// One `#RRGGBB` per line. Only keeps the backgrounds, the rest is
+    /// picked again on import.
+    Hex,
+}
+
+impl PaletteFormat {
+    pub const ALL: [PaletteFormat; 4] = [PaletteFormat::Json, PaletteFormat::Gpl, PaletteFormat::Ase, PaletteFormat::Hex];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaletteFormat::Json => "JSON",
+            PaletteFormat::Gpl => "GPL",
+            PaletteFormat::Ase => "ASE",
+            PaletteFormat::Hex => "Hex",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Json => "json",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Ase => "ase",
+            PaletteFormat::Hex => "hex",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            PaletteFormat::Json => "application/json",
+            PaletteFormat::Ase => "application/octet-stream",
+            PaletteFormat::Gpl | PaletteFormat::Hex => "text/plain",
+        }
+    }
+
+    /// Guesses the format from a file extension, text files are hex lists.
+    pub fn from_file_name(name: &str) -> Option<PaletteFormat> {
+        let extension = name.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "json" => Some(PaletteFormat::Json),
+            "gpl" => Some(PaletteFormat::Gpl),
+            "ase" => Some(PaletteFormat::Ase),
+            "hex" | "txt" => Some(PaletteFormat::Hex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteError {
+    UnsupportedFormat,
+    /// Recognised format, but not laid out the way it should be.
+    Invalid(String),
+    /// Parsed, but without a single usable color.
+    Empty,
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::UnsupportedFormat => write!(f, "Unsupported palette format"),
+            PaletteError::Invalid(reason) => write!(f, "Not a valid palette: {}", reason),
+            PaletteError::Empty => write!(f, "Palette has no colors"),
+        }
+    }
+}
+
+/// The JSON palette format, also how palettes are kept in preferences.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaletteJson {
+    pub name: String,
+    pub colors: Vec<EntryJson>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntryJson {
+    pub background: String,
+    pub foreground: String,
+    pub accent: String,
+}
+
+impl From<ColorList> for PaletteJson {
+    fn from(list: ColorList) -> Self {
+        PaletteJson {
+            name: list.name().to_string(),
+            colors: list.entries().iter()
+                .map(|entry| EntryJson {
+                    background: entry.bg_hex.clone(),
+                    foreground: entry.fg_hex.clone(),
+                    accent: entry.accent.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<PaletteJson> for ColorList {
+    type Error = PaletteError;
+
+    fn try_from(json: PaletteJson) -> Result<Self, PaletteError> {
+        let entries = json.colors.iter()
+            .map(|entry| Ok(ColorEntry::from_colors(
+                parse_hex(&entry.background)?,
+                parse_hex(&entry.foreground)?,
+                parse_hex(&entry.accent)?,
+            )))
+            .collect::<Result<Vec<_>, PaletteError>>()?;
+        ColorList::from_entries(json.name, entries).ok_or(PaletteError::Empty)
+    }
+}
+
+/// Reads a palette file. `file_name` picks the format and, where the file
+/// doesn't carry one, the palette's name.
+pub fn parse(bytes: &[u8], file_name: &str) -> Result<ColorList, PaletteError> {
+    let format = PaletteFormat::from_file_name(file_name).ok_or(PaletteError::UnsupportedFormat)?;
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem).to_string();
+
+    let (name, swatches) = match format {
+        PaletteFormat::Json => return parse_json(bytes, stem),
+        PaletteFormat::Gpl => parse_gpl(&String::from_utf8_lossy(bytes))?,
+        PaletteFormat::Ase => (None, parse_ase(bytes)?),
+        PaletteFormat::Hex => (None, parse_hex_list(&String::from_utf8_lossy(bytes))),
+    };
+    from_swatches(name.unwrap_or(stem), swatches)
+}
+
+pub fn encode(list: &ColorList, format: PaletteFormat) -> Vec<u8> {
+    match format {
+        PaletteFormat::Json => serde_json::to_vec_pretty(&PaletteJson::from(list.clone())).unwrap_or_default(),
+        PaletteFormat::Gpl => encode_gpl(list).into_bytes(),
+        PaletteFormat::Ase => encode_ase(list),
+        PaletteFormat::Hex => list.entries().iter()
+            .map(|entry| format!("{}\n", entry.bg_rgb))
+            .collect::<String>()
+            .into_bytes(),
+    }
+}
+
+/// `Ocean palette` becomes `Ocean palette.gpl`.
+pub fn file_name(list: &ColorList, format: PaletteFormat) -> String {
+    let stem: String = list.name().chars()
+        .filter(|c| !matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect();
+    let stem = if stem.trim().is_empty() { "palette" } else { stem.trim() };
+    format!("{}.{}", stem, format.extension())
+}
+
+fn parse_hex(hex: &str) -> Result<HexColor, PaletteError> {
+    let hex = hex.trim();
+    let hex = if hex.starts_with('#') { hex.to_string() } else { format!("#{}", hex) };
+    HexColor::parse_rgb(&hex).map_err(|e| PaletteError::Invalid(format!("{}: {}", hex, e)))
+}
+
+// full entries, or a bare array of hex strings
+fn parse_json(bytes: &[u8], name: String) -> Result<ColorList, PaletteError> {
+    if let Ok(json) = serde_json::from_slice::<PaletteJson>(bytes) {
+        return ColorList::try_from(json);
+    }
+    let hexes: Vec<String> = serde_json::from_slice(bytes).map_err(|e| PaletteError::Invalid(e.to_string()))?;
+    let swatches = hexes.iter()
+        .map(|hex| parse_hex(hex).map(|color| (None, color)))
+        .collect::<Result<Vec<_>, PaletteError>>()?;
+    from_swatches(name, swatches)
+}
+
+// entries written by `encode` come back whole, any other list of colors is
+// turned into entries like an extracted palette
+fn from_swatches(name: String, swatches: Swatches) -> Result<ColorList, PaletteError> {
+    let has_roles = !swatches.is_empty() && swatches.len().is_multiple_of(3)
+        && swatches.iter().enumerate().all(|(i, (swatch_name, _))| {
+            swatch_name.as_deref().is_some_and(|n| n.ends_with(ROLES[i % 3]))
+        });
+
+    let list = if has_roles {
+        let entries = swatches.chunks(3)
+            .map(|entry| ColorEntry::from_colors(entry[0].1, entry[1].1, entry[2].1))
+            .collect();
+        ColorList::from_entries(name, entries)
+    } else {
+        let colors: Vec<HexColor> = swatches.into_iter().map(|(_, color)| color).collect();
+        ColorList::from_colors(name, &colors)
+    };
+    list.ok_or(PaletteError::Empty)
+}
+
+fn swatches(list: &ColorList) -> Vec<(String, HexColor)> {
+    list.entries().iter()
+        .enumerate()
+        .flat_map(|(i, entry)| {
+            [entry.bg_rgb, entry.fg_rgb, entry.accent_rgb()]
+                .into_iter()
+                .zip(ROLES)
+                .map(move |(color, role)| (format!("{} {}", i + 1, role), color))
+        })
+        .collect()
+}
+
+fn parse_hex_list(text: &str) -> Swatches {
+    text.split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|token| !token.is_empty())
+        .filter_map(|token| parse_hex(token).ok())
+        .map(|color| (None, color))
+        .collect()
+}
+
+fn parse_gpl(text: &str) -> Result<(Option<String>, Swatches), PaletteError> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(PaletteError::Invalid(String::from("missing GIMP Palette header")));
+    }
+
+    let mut name = None;
+    let mut swatches = Vec::new();
+    for line in lines.map(str::trim) {
+        if let Some(value) = line.strip_prefix("Name:") {
+            name = Some(value.trim().to_string()).filter(|n| !n.is_empty());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let mut channel = || fields.next().and_then(|v| v.parse::<u8>().ok());
+        let (r, g, b) = match (channel(), channel(), channel()) {
+            (Some(r), Some(g), Some(b)) => (r, g, b),
+            _ => return Err(PaletteError::Invalid(format!("bad color line \"{}\"", line))),
+        };
+        let swatch_name = fields.collect::<Vec<_>>().join(" ");
+        swatches.push((Some(swatch_name).filter(|n| !n.is_empty()), HexColor::rgb(r, g, b)));
+    }
+    Ok((name, swatches))
+}
+
+fn encode_gpl(list: &ColorList) -> String {
+    let mut text = format!("GIMP Palette\nName: {}\nColumns: 3\n#\n", list.name());
+    for (name, color) in swatches(list) {
+        text.push_str(&format!("{:3} {:3} {:3}\t{}\n", color.r, color.g, color.b, name));
+    }
+    text
+}
+
+const ASE_COLOR: u16 = 0x0001;
+
+// big endian throughout. Groups are flattened, Lab swatches are skipped.
+// Offsets come from the file, so they're added checked.
+fn parse_ase(bytes: &[u8]) -> Result<Swatches, PaletteError> {
+    let invalid = |reason: &str| PaletteError::Invalid(reason.to_string());
+    if bytes.get(..4) != Some(b"ASEF") {
+        return Err(invalid("missing ASEF signature"));
+    }
+    let read = |at: usize, len: usize| bytes.get(at..at.checked_add(len)?);
+    let read_u16 = |at: usize| read(at, 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let read_u32 = |at: usize| read(at, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let read_f32 = |at: usize| read_u32(at).map(f32::from_bits);
+    let offset = |at: usize, by: usize| at.checked_add(by).ok_or_else(|| invalid("offset out of range"));
+
+    let blocks = read_u32(8).ok_or_else(|| invalid("truncated header"))? as usize;
+    // every block has a 6 byte header, so the rest of the file caps the count
+    if blocks > (bytes.len() - 12) / 6 {
+        return Err(invalid("more blocks than the file holds"));
+    }
+    let mut at: usize = 12;
+    let mut swatches = Vec::new();
+    for _ in 0..blocks {
+        let kind = read_u16(at).ok_or_else(|| invalid("truncated block"))?;
+        let length = read_u32(offset(at, 2)?).ok_or_else(|| invalid("truncated block"))? as usize;
+        let body = offset(at, 6)?;
+        at = offset(body, length)?;
+        if at > bytes.len() {
+            return Err(invalid("truncated block"));
+        }
+        if kind != ASE_COLOR {
+            continue;
+        }
+
+        // name: UTF-16 length including the terminator, then the units
+        let units = read_u16(body).ok_or_else(|| invalid("truncated color"))? as usize;
+        let name_at = offset(body, 2)?;
+        let model_at = offset(name_at, units * 2)?;
+        let name = bytes.get(name_at..model_at).ok_or_else(|| invalid("truncated color"))?;
+        let name: Vec<u16> = name.chunks_exact(2).map(|unit| u16::from_be_bytes([unit[0], unit[1]])).collect();
+        let name = String::from_utf16_lossy(&name).trim_end_matches('\0').to_string();
+        let model = read(model_at, 4).ok_or_else(|| invalid("truncated color"))?;
+        let value = |i: usize| read_f32(offset(model_at, 4 + i * 4)?).ok_or_else(|| invalid("truncated color"));
+
+        let rgb = match model {
+            b"RGB " => [value(0)?, value(1)?, value(2)?],
+            b"Gray" => [value(0)?; 3],
+            b"CMYK" => {
+                let (c, m, y, k) = (value(0)?, value(1)?, value(2)?, value(3)?);
+                [(1.0 - c) * (1.0 - k), (1.0 - m) * (1.0 - k), (1.0 - y) * (1.0 - k)]
+            }
+            _ => continue,
+        };
+        let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        swatches.push((Some(name).filter(|n| !n.is_empty()), HexColor::rgb(r, g, b)));
+    }
+    Ok(swatches)
+}
+
+fn encode_ase(list: &ColorList) -> Vec<u8> {
+    let swatches = swatches(list);
+    let mut out = Vec::new();
+    out.extend_from_slice(b"ASEF");
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(swatches.len() as u32).to_be_bytes());
+
+    for (name, color) in swatches {
+        let mut units: Vec<u16> = name.encode_utf16().collect();
+        units.push(0);
+
+        let mut block = Vec::new();
+        block.extend_from_slice(&(units.len() as u16).to_be_bytes());
+        for unit in units {
+            block.extend_from_slice(&unit.to_be_bytes());
+        }
+        block.extend_from_slice(b"RGB ");
+        for c in [color.r, color.g, color.b] {
+            block.extend_from_slice(&(c as f32 / 255.0).to_be_bytes());
+        }
+        // global color
+        block.extend_from_slice(&0u16.to_be_bytes());
+
+        out.extend_from_slice(&ASE_COLOR.to_be_bytes());
+        out.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        out.extend_from_slice(&block);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> ColorList {
+        let entries = vec![
+            ColorEntry::from_colors(HexColor::rgb(0x25, 0x0e, 0xae), HexColor::rgb(255, 255, 255), HexColor::rgb(0x46, 0xff, 0x5d)),
+            ColorEntry::from_colors(HexColor::rgb(0xf0, 0xe0, 0xd0), HexColor::rgb(0, 0, 0), HexColor::rgb(0x80, 0x10, 0x20)),
+        ];
+        ColorList::from_entries(String::from("Sample"), entries).unwrap()
+    }
+
+    fn backgrounds(list: &ColorList) -> Vec<HexColor> {
+        list.entries().iter().map(|entry| entry.bg_rgb).collect()
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let list = sample();
+        for format in [PaletteFormat::Json, PaletteFormat::Gpl, PaletteFormat::Ase] {
+            let parsed = parse(&encode(&list, format), &file_name(&list, format)).unwrap();
+            assert_eq!(parsed, list, "{}", format.name());
+        }
+
+        // hex lists only keep the backgrounds
+        let hex = PaletteFormat::Hex;
+        let parsed = parse(&encode(&list, hex), &file_name(&list, hex)).unwrap();
+        assert_eq!(parsed.name(), "Sample");
+        assert_eq!(backgrounds(&parsed), backgrounds(&list));
+    }
+
+    #[test]
+    fn refuses_truncated_ase() {
+        let ase = encode(&sample(), PaletteFormat::Ase);
+        for len in [4, 10, 14, 20, ase.len() - 1] {
+            assert!(matches!(parse_ase(&ase[..len]), Err(PaletteError::Invalid(_))), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn refuses_oversized_ase() {
+        let mut header = b"ASEF\0\x01\0\0".to_vec();
+        header.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        assert_eq!(parse_ase(&header), Err(PaletteError::Invalid(String::from("more blocks than the file holds"))));
+        header[8..12].copy_from_slice(&1u32.to_be_bytes());
+
+        // a block longer than the file
+        let mut huge = header.clone();
+        huge.extend_from_slice(&ASE_COLOR.to_be_bytes());
+        huge.extend_from_slice(&u32::MAX.to_be_bytes());
+        huge.extend_from_slice(&u16::MAX.to_be_bytes());
+        assert!(matches!(parse_ase(&huge), Err(PaletteError::Invalid(_))));
+
+        // a name longer than the file
+        let mut long_name = header;
+        long_name.extend_from_slice(&ASE_COLOR.to_be_bytes());
+        long_name.extend_from_slice(&8u32.to_be_bytes());
+        long_name.extend_from_slice(&u16::MAX.to_be_bytes());
+        long_name.extend_from_slice(&[0; 6]);
+        assert!(matches!(parse_ase(&long_name), Err(PaletteError::Invalid(_))));
+    }
+}
@@ -0,0 +1,179 @@
+/// Something placed along 0-1 in a `PointList`, a gradient stop or a
+/// curve point.
+pub trait Point: Copy {
+    fn position(&self) -> f32;
+
+    /// The same point at `position`, which is already in 0-1.
+    fn with_position(self, position: f32) -> Self;
+}
+
+/// 2 to `N` points kept in position order. Fixed size, so the curves and
+/// gradients holding one stay `Copy`.
+#[derive(Copy, Clone, Debug)]
+pub struct PointList<P, const N: usize> {
+    points: [P; N],
+    len: usize,
+}
+
+impl<P: Point, const N: usize> PointList<P, N> {
+    /// The first `N` of `points`, sorted. `None` for fewer than two.
+    /// Saved curves and gradients are read through this, so they get the
+    /// same cap and order as edits.
+    pub fn new(points: &[P]) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+        let mut list = PointList { points: [points[0]; N], len: points.len().min(N) };
+        list.points[..list.len].copy_from_slice(&points[..list.len]);
+        list.sort();
+        Some(list)
+    }
+
+    pub fn points(&self) -> &[P] {
+        &self.points[..self.len]
+    }
+
+    /// Replaces the point at `index`. It stays between its neighbours, so
+    /// points keep their order and index while being dragged.
+    pub fn set(&mut self, index: usize, point: P) {
+        if index >= self.len {
+            return;
+        }
+        let min = if index > 0 { self.points[index - 1].position() } else { 0.0 };
+        let max = if index + 1 < self.len { self.points[index + 1].position() } else { 1.0 };
+        self.points[index] = point.with_position(point.position().clamp(min, max));
+    }
+
+    /// Middle of the widest gap between points, where a new one goes.
+    /// `None` when the list is full.
+    pub fn widest_gap(&self) -> Option<f32> {
+        if self.len >= N {
+            return None;
+        }
+        let (after, gap) = self.points()
+            .windows(2)
+            .enumerate()
+            .map(|(i, pair)| (i, pair[1].position() - pair[0].position()))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0));
+        Some(self.points[after].position() + gap / 2.0)
+    }
+
+    /// Adds `point` in order, after any at the same position. Returns its
+    /// index, `None` when the list is full.
+    pub fn insert(&mut self, point: P) -> Option<usize> {
+        if self.len >= N {
+            return None;
+        }
+        let index = self.points().partition_point(|p| p.position() <= point.position());
+        self.points.copy_within(index..self.len, index + 1);
+        self.points[index] = point;
+        self.len += 1;
+        Some(index)
+    }
+
+    /// Keeps at least two points, both ends are needed.
+    pub fn remove(&mut self, index: usize) {
+        if index < self.len && self.len > 2 {
+            self.points.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        }
+    }
+
+    /// Index of the point starting the span `position` is in, or the end
+    /// point when it's past either end.
+    pub fn span(&self, position: f32) -> Result<usize, P> {
+        let points = self.points();
+        match points.iter().position(|point| point.position() >= position) {
+            None => Err(points[points.len() - 1]),
+            Some(0) => Err(points[0]),
+            Some(i) => Ok(i - 1),
+        }
+    }
+
+    fn sort(&mut self) {
+        self.points[..self.len].sort_by(|a, b| a.position().total_cmp(&b.position()));
+    }
+}
+
+// slots past `len` are leftovers, only the points count
+impl<P: Point + PartialEq, const N: usize> PartialEq for PointList<P, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.points() == other.points()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct At(f32);
+
+    impl Point for At {
+        fn position(&self) -> f32 {
+            self.0
+        }
+
+        fn with_position(self, position: f32) -> Self {
+            At(position)
+        }
+    }
+
+    fn positions<const N: usize>(list: &PointList<At, N>) -> Vec<f32> {
+        list.points().iter().map(|point| point.0).collect()
+    }
+
+    #[test]
+    fn keeps_the_first_n_in_order() {
+        assert_eq!(PointList::<At, 4>::new(&[At(0.5)]), None);
+
+        let list = PointList::<At, 3>::new(&[At(1.0), At(0.0), At(0.5), At(0.25)]).unwrap();
+        assert_eq!(positions(&list), [0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn inserts_in_order_until_full() {
+        let mut list = PointList::<At, 4>::new(&[At(0.0), At(1.0)]).unwrap();
+        assert_eq!(list.insert(At(0.75)), Some(1));
+        assert_eq!(list.insert(At(0.25)), Some(1));
+        assert_eq!(positions(&list), [0.0, 0.25, 0.75, 1.0]);
+        assert_eq!(list.widest_gap(), None);
+        assert_eq!(list.insert(At(0.5)), None);
+        assert_eq!(list.points().len(), 4);
+    }
+
+    #[test]
+    fn splits_the_widest_gap() {
+        let list = PointList::<At, 4>::new(&[At(0.0), At(0.2), At(1.0)]).unwrap();
+        assert_eq!(list.widest_gap(), Some(0.6));
+    }
+
+    #[test]
+    fn keeps_points_between_their_neighbours() {
+        let mut list = PointList::<At, 4>::new(&[At(0.0), At(0.5), At(1.0)]).unwrap();
+        list.set(1, At(2.0));
+        assert_eq!(positions(&list), [0.0, 1.0, 1.0]);
+        list.set(0, At(-1.0));
+        assert_eq!(positions(&list)[0], 0.0);
+        list.set(5, At(0.5));
+        assert_eq!(positions(&list), [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn keeps_both_ends() {
+        let mut list = PointList::<At, 4>::new(&[At(0.0), At(0.5), At(1.0)]).unwrap();
+        list.remove(1);
+        list.remove(0);
+        assert_eq!(positions(&list), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn finds_spans() {
+        let list = PointList::<At, 4>::new(&[At(0.2), At(0.5), At(0.8)]).unwrap();
+        assert_eq!(list.span(0.1), Err(At(0.2)));
+        assert_eq!(list.span(0.3), Ok(0));
+        assert_eq!(list.span(0.6), Ok(1));
+        assert_eq!(list.span(0.9), Err(At(0.8)));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::color_management::{ColorList, PaletteSource};
+use crate::effects::{Adjustments, EffectStack};
+
+const STORAGE_KEY: &str = "preferences";
+
+/// What survives a reload, kept in `localStorage`. Every field has a
+/// default so preferences written by an older build still load.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    /// Top left corner of each draggable panel, by panel id.
+    pub panels: HashMap<String, (f64, f64)>,
+    /// Ids of collapsed panels.
+    pub collapsed: HashSet<String>,
+    /// Ids of panels in the dock rail.
+    pub docked: HashSet<String>,
+    /// Panel snap grid in pixels, 0 when off.
+    pub grid: u32,
+    pub color_index: usize,
+    pub palette_source: PaletteSource,
+    /// Imported palettes, in the JSON palette format.
+    pub palettes: Vec<ColorList>,
+    /// Whether palette colors failing WCAG AA are adjusted before use.
+    pub readable_colors: bool,
+    pub effects: EffectStack,
+    pub adjustments: Adjustments,
+    pub fill: bool,
+}
+
+thread_local! {
+    // read once, the atoms it seeds are only initialised on startup
+    static STORED: Preferences = load();
+}
+
+/// The preferences saved by the last session, defaults if there are none.
+pub fn stored() -> Preferences {
+    STORED.with(|preferences| preferences.clone())
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window().and_then(|win| win.local_storage().ok().flatten())
+}
+
+fn load() -> Preferences {
+    local_storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten())
+        .and_then(|json| match serde_json::from_str(&json) {
+            Ok(preferences) => Some(preferences),
+            Err(e) => {
+                log::warn!("ignoring stored preferences: {}", e);
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// Serialises `preferences`, `None` if that fails.
+pub fn to_json(preferences: &Preferences) -> Option<String> {
+    serde_json::to_string(preferences).ok()
+}
+
+pub fn store(json: &str) {
+    // storage can be full or disabled, losing preferences isn't worth more than a log
+    if let Some(Err(e)) = local_storage().map(|storage| storage.set_item(STORAGE_KEY, json)) {
+        log::warn!("couldn't store preferences: {:?}", e);
+    }
+}
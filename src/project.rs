@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::color_management::{ColorList, PaletteSource};
+use crate::curves::Curves;
+use crate::effects::{Adjustments, EffectStack};
+use crate::export::{self, ExportFormat};
+use crate::image_codec;
+use crate::image_decode::{download_bytes, DecodeError};
+use crate::lut::{Lut, LutError};
+use crate::render_pipeline::WebImage;
+
+/// Version written by this build. Bump it when the format changes and add
+/// a step to `MIGRATIONS` that upgrades the previous version's JSON.
+pub const PROJECT_VERSION: u32 = 3;
+
+// `MIGRATIONS[i]` turns a version `i + 1` project into version `i + 2`
+const MIGRATIONS: &[fn(Value) -> Value] = &[add_luts, add_curves];
+
+pub const PROJECT_MIME: &str = "application/json";
+
+/// A saved editing session: every image with its effects, which one is
+/// active, and the UI state around them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub images: Vec<ProjectImage>,
+    /// Index into `images`.
+    pub active: Option<usize>,
+    #[serde(default)]
+    pub fill: bool,
+    #[serde(default)]
+    pub color_index: usize,
+    #[serde(default)]
+    pub palette_source: PaletteSource,
+    /// The imported palette when `palette_source` is `Saved`, its index
+    /// only means something to the browser that saved the project.
+    #[serde(default)]
+    pub palette: Option<ColorList>,
+    #[serde(default)]
+    pub readable_colors: bool,
+    /// Top left corner of each draggable panel, by panel id.
+    #[serde(default)]
+    pub panels: HashMap<String, (f64, f64)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectImage {
+    pub name: String,
+    pub effects: EffectStack,
+    pub adjustments: Adjustments,
+    /// The image's LUT as `.cube` text.
+    pub lut: Option<String>,
+    pub source: ImageSource,
+    /// Renderer id while saving, not part of the file.
+    #[serde(skip)]
+    pub id: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ImageSource {
+    /// Base64 PNG of the decoded pixels, so the project opens on its own.
+    Embedded { png: String },
+    /// Only the file name is stored, the image is picked again on open.
+    Referenced,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProjectError {
+    /// Not JSON, or not shaped like a project.
+    Invalid(String),
+    /// Saved by a newer build than this one.
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// A referenced image wasn't among the opened files.
+    MissingImage(String),
+    Image(String, DecodeError),
+    Lut(String, LutError),
+}
+
+impl std::fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectError::Invalid(reason) => write!(f, "Not a project file: {}", reason),
+            ProjectError::UnsupportedVersion { found, supported } => {
+                write!(f, "Project version {} is newer than {}", found, supported)
+            }
+            ProjectError::MissingImage(name) => write!(f, "Open {} along with the project", name),
+            ProjectError::Image(name, e) => write!(f, "{}: {}", name, e),
+            ProjectError::Lut(name, e) => write!(f, "{}: {}", name, e),
+        }
+    }
+}
+
+impl Project {
+    /// Parses a project file, upgrading older versions first.
+    pub fn parse(bytes: &[u8]) -> Result<Project, ProjectError> {
+        let mut value: Value = serde_json::from_slice(bytes)
+            .map_err(|e| ProjectError::Invalid(e.to_string()))?;
+        let version = value.get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| ProjectError::Invalid(String::from("missing version")))? as u32;
+
+        if version > PROJECT_VERSION {
+            return Err(ProjectError::UnsupportedVersion { found: version, supported: PROJECT_VERSION });
+        }
+        for migrate in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+            value = migrate(value);
+        }
+        value["version"] = Value::from(PROJECT_VERSION);
+
+        let mut project: Project = serde_json::from_value(value).map_err(|e| ProjectError::Invalid(e.to_string()))?;
+        project.active = project.active.filter(|&i| i < project.images.len());
+        Ok(project)
+    }
+
+    /// Embeds `img` as the pixels of `images[index]`.
+    pub fn embed(&mut self, index: usize, img: &WebImage) {
+        match export::encode(img, ExportFormat::Png) {
+            Ok(png) => self.images[index].source = ImageSource::Embedded { png: BASE64.encode(png) },
+            Err(e) => log::error!("couldn't embed {}: {}", self.images[index].name, e),
+        }
+    }
+
+    /// Downloads the project, named after the active image.
+    pub fn save(&self) {
+        let active = self.active.and_then(|i| self.images.get(i));
+        let file_name = file_name(active.map(|image| image.name.as_str()));
+        match serde_json::to_vec_pretty(self) {
+            Ok(bytes) => download_bytes(&bytes, PROJECT_MIME, &file_name),
+            Err(e) => log::error!("couldn't write project: {}", e),
+        }
+    }
+}
+
+impl ProjectImage {
+    /// Decodes embedded pixels. `None` for referenced images.
+    pub fn decode_embedded(&self) -> Option<Result<WebImage, ProjectError>> {
+        match &self.source {
+            ImageSource::Embedded { png } => Some(
+                BASE64.decode(png)
+                    .map_err(|_| DecodeError::CorruptData)
+                    .and_then(|bytes| image_codec::decode(&bytes))
+                    .map_err(|e| ProjectError::Image(self.name.clone(), e)),
+            ),
+            ImageSource::Referenced => None,
+        }
+    }
+
+    pub fn decode_lut(&self) -> Result<Option<Lut>, ProjectError> {
+        self.lut.as_deref()
+            .map(|text| Lut::parse(text, &self.name).map_err(|e| ProjectError::Lut(self.name.clone(), e)))
+            .transpose()
+    }
+}
+
+// version 2: images have a LUT and a LUT intensity, at full strength
+// until one is loaded. Some version 1 files already have the intensity,
+// so the migrations only fill in what's missing.
+fn add_luts(mut value: Value) -> Value {
+    for image in images(&mut value) {
+        image.entry("lut").or_insert(Value::Null);
+        if let Some(adjustments) = image.get_mut("adjustments").and_then(Value::as_object_mut) {
+            adjustments.entry("lut_intensity").or_insert(Value::from(1.0));
+        }
+    }
+    value
+}
+
+// version 3: images have levels and curves, flat before
+fn add_curves(mut value: Value) -> Value {
+    let curves = serde_json::to_value(Curves::default()).unwrap_or(Value::Null);
+    for image in images(&mut value) {
+        if let Some(adjustments) = image.get_mut("adjustments").and_then(Value::as_object_mut) {
+            adjustments.entry("curves").or_insert_with(|| curves.clone());
+        }
+    }
+    value
+}
+
+fn images(value: &mut Value) -> impl Iterator<Item = &mut Map<String, Value>> {
+    value.get_mut("images")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+/// `photo.jpg` becomes `photo.project.json`.
+pub fn file_name(source: Option<&str>) -> String {
+    let stem = source
+        .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("untitled");
+    format!("{}.project.json", stem)
+}
+
+/// Whether a file by this name could be a project, see `is_project_file`.
+pub fn is_project_name(name: &str) -> bool {
+    name.to_lowercase().ends_with(".json")
+}
+
+/// Whether `bytes` are JSON with a project version, other JSON (a
+/// palette, say) isn't taken for a project.
+pub fn is_project_file(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(bytes)
+        .is_ok_and(|value| value.get("version").is_some_and(Value::is_u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project(active: Option<usize>) -> Project {
+        let image = |name: &str| ProjectImage {
+            name: name.to_string(),
+            effects: EffectStack::default(),
+            adjustments: Adjustments::default(),
+            lut: None,
+            source: ImageSource::Referenced,
+            id: 0,
+        };
+        Project {
+            version: PROJECT_VERSION,
+            images: vec![image("a.png"), image("b.png")],
+            active,
+            fill: true,
+            color_index: 3,
+            palette_source: PaletteSource::Image,
+            palette: None,
+            readable_colors: false,
+            panels: HashMap::new(),
+        }
+    }
+
+    // an image as version 1 saved it, before LUTs and curves
+    fn version_1(adjustments: Value) -> Vec<u8> {
+        json!({
+            "version": 1,
+            "images": [{
+                "name": "a.png",
+                "effects": EffectStack::default(),
+                "adjustments": adjustments,
+                "source": { "type": "referenced" },
+            }],
+            "active": 0,
+        }).to_string().into_bytes()
+    }
+
+    fn sliders() -> Value {
+        json!({ "exposure": 1.0, "brightness": 0.0, "contrast": 1.0, "gamma": 1.0, "saturation": 1.0 })
+    }
+
+    #[test]
+    fn round_trips() {
+        let saved = project(Some(1));
+        let bytes = serde_json::to_vec(&saved).unwrap();
+        assert!(is_project_file(&bytes));
+        assert_eq!(Project::parse(&bytes), Ok(saved));
+    }
+
+    #[test]
+    fn drops_an_active_image_past_the_end() {
+        let bytes = serde_json::to_vec(&project(Some(2))).unwrap();
+        assert_eq!(Project::parse(&bytes).unwrap().active, None);
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let project = Project::parse(&version_1(sliders())).unwrap();
+        assert_eq!(project.version, PROJECT_VERSION);
+        let image = &project.images[0];
+        assert_eq!(image.lut, None);
+        let mut expected = Adjustments::default();
+        expected.exposure = 1.0;
+        assert_eq!(image.adjustments, expected);
+    }
+
+    #[test]
+    fn migrations_fill_in_the_new_fields() {
+        let value: Value = serde_json::from_slice(&version_1(sliders())).unwrap();
+        let value = MIGRATIONS.iter().fold(value, |value, migrate| migrate(value));
+        let image = &value["images"][0];
+        assert_eq!(image["lut"], Value::Null);
+        assert_eq!(image["adjustments"]["lut_intensity"], json!(1.0));
+        assert_eq!(image["adjustments"]["curves"], serde_json::to_value(Curves::default()).unwrap());
+    }
+
+    #[test]
+    fn migrations_keep_what_is_already_there() {
+        let mut adjustments = sliders();
+        adjustments["lut_intensity"] = json!(0.5);
+        let project = Project::parse(&version_1(adjustments)).unwrap();
+        assert_eq!(project.images[0].adjustments.lut_intensity, 0.5);
+    }
+
+    #[test]
+    fn refuses_other_files() {
+        let newer = json!({ "version": PROJECT_VERSION + 1, "images": [] }).to_string();
+        assert_eq!(
+            Project::parse(newer.as_bytes()),
+            Err(ProjectError::UnsupportedVersion { found: PROJECT_VERSION + 1, supported: PROJECT_VERSION }),
+        );
+
+        let palette = json!({ "name": "Ocean", "colors": [] }).to_string();
+        assert!(!is_project_file(palette.as_bytes()));
+        assert!(matches!(Project::parse(palette.as_bytes()), Err(ProjectError::Invalid(_))));
+        assert!(!is_project_file(b"not json"));
+    }
+
+    #[test]
+    fn names_projects_after_the_image() {
+        assert_eq!(file_name(Some("photo.jpg")), "photo.project.json");
+        assert_eq!(file_name(Some(".jpg")), "untitled.project.json");
+        assert_eq!(file_name(None), "untitled.project.json");
+        assert!(is_project_name("Photo.Project.JSON"));
+    }
+}
@@ -1,15 +1,27 @@
 use std::{iter, mem};
+use std::cell::Cell;
+use std::rc::Rc;
 use bytemuck::{Pod, Zeroable};
 use wgpu::{util::DeviceExt, TextureUsages, Sampler};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopBuilder},
     window::{Window, WindowBuilder},
-    platform::web
 };
 
 use wasm_bindgen_futures::spawn_local;
 
+use crate::color_management::{ColorEntry, ColorList};
+use crate::color_space;
+use crate::curves::{Curves, CURVE_WIDTH};
+use crate::effects::{Adjustment, Adjustments, Effect, EffectLayer, EffectParam, EffectParams, EffectStack, MAX_LAYERS};
+use crate::export::{self, ExportOptions};
+use crate::gradient::{Gradient, GRADIENT_WIDTH};
+use crate::histogram::{self, Histogram};
+use crate::lut::{Lut, LutKind};
+use crate::metadata::ImageMetadata;
+use crate::project::Project;
+
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -66,30 +78,36 @@ impl Vertex {
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InputUniform {
-    effect: i32,
     fill_mode: i32,
     window_ratio: f32,
     img_ratio: f32,
+    _padding: f32,
 }
 
 impl InputUniform {
     fn new(window_ratio: f32) -> Self {
         Self {
-            effect: 0,
             fill_mode: 0,
             window_ratio: window_ratio,
-            img_ratio: 0f32
+            img_ratio: 0f32,
+            _padding: 0f32,
         }
     }
 
-    fn step(&mut self) {
-        if self.effect < 6 {
-            self.effect += 1;
-        } else {
-            self.effect = 0;
+    // fills the whole target, used when the target already has the image's aspect ratio
+    fn stretched(ratio: f32) -> Self {
+        Self {
+            fill_mode: 2,
+            window_ratio: ratio,
+            img_ratio: ratio,
+            _padding: 0f32,
         }
     }
 
+    fn set_fill(&mut self, fill: bool) {
+        self.fill_mode = if fill { 1 } else { 0 };
+    }
+
     fn toggle_fill(&mut self) {
         if self.fill_mode == 0 {
             self.fill_mode = 1;
@@ -100,10 +118,326 @@ impl InputUniform {
     
 }
 
+// mirrored by the LayerUniform struct in effect-shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniform {
+    effect: i32,
+    follow_palette: i32,
+    _padding: [i32; 2],
+    params: EffectParams,
+}
+
+impl LayerUniform {
+    fn new(layer: &EffectLayer) -> Self {
+        // picked as sRGB, blended in linear light like the palette colors
+        let linear = |[r, g, b, _]: [f32; 4]| color_space::linear_rgba([r, g, b].map(f64::from));
+        let params = layer.settings.current();
+        Self {
+            effect: layer.settings.effect.index() as i32,
+            follow_palette: layer.follow_palette as i32,
+            _padding: [0; 2],
+            params: EffectParams {
+                tint: linear(params.tint),
+                shadow: linear(params.shadow),
+                midtone: linear(params.midtone),
+                ..*params
+            },
+        }
+    }
+}
+
+// the active palette in linear RGB, mirrored by PaletteUniform in effect-shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PaletteUniform {
+    bg: [f32; 4],
+    fg: [f32; 4],
+    accent: [f32; 4],
+}
+
+impl PaletteUniform {
+    fn new(colors: &ColorEntry) -> Self {
+        let [bg, fg, accent] = colors.linear_rgba();
+        Self { bg, fg, accent }
+    }
+}
+
+// mirrored by the AdjustmentUniform struct in effect-shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AdjustmentUniform {
+    exposure: f32,
+    brightness: f32,
+    contrast: f32,
+    gamma: f32,
+    saturation: f32,
+    lut_intensity: f32,
+    _padding: [f32; 2],
+}
+
+impl AdjustmentUniform {
+    fn new(adjustments: &Adjustments) -> Self {
+        Self {
+            exposure: adjustments.exposure,
+            brightness: adjustments.brightness,
+            contrast: adjustments.contrast,
+            gamma: adjustments.gamma,
+            saturation: adjustments.saturation,
+            lut_intensity: adjustments.lut_intensity,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// the baked levels and curves, bound at group 3 of the adjust pipeline
+struct CurvesBinding {
+    bind_group: wgpu::BindGroup,
+    texture: Texture,
+    // what's in `texture`, so it's only uploaded on changes
+    baked: Option<Curves>,
+}
+
+impl CurvesBinding {
+    fn upload(&mut self, queue: &wgpu::Queue, curves: Curves) {
+        if self.baked == Some(curves) {
+            return;
+        }
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            &curves.bake(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * CURVE_WIDTH),
+                rows_per_image: std::num::NonZeroU32::new(1),
+            },
+            curves_size(),
+        );
+        self.baked = Some(curves);
+    }
+}
+
+fn curves_size() -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: CURVE_WIDTH,
+        height: 1,
+        depth_or_array_layers: 1,
+    }
+}
+
+// one per effect stack slot, bound at group 2 of the effect pipeline
+struct LayerBinding {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // lookup texture of the gradient effects, GRADIENT_WIDTH x 1 since
+    // WebGL2 has no 1D textures
+    gradient_texture: Texture,
+    // what's in `gradient_texture`, so it's only uploaded on changes
+    baked: Option<Gradient>,
+}
+
+impl LayerBinding {
+    fn upload_gradient(&mut self, queue: &wgpu::Queue, gradient: Gradient) {
+        if self.baked == Some(gradient) {
+            return;
+        }
+        queue.write_texture(
+            self.gradient_texture.as_image_copy(),
+            &gradient.bake(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * GRADIENT_WIDTH),
+                rows_per_image: std::num::NonZeroU32::new(1),
+            },
+            gradient_size(),
+        );
+        self.baked = Some(gradient);
+    }
+}
+
+fn gradient_size() -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: GRADIENT_WIDTH,
+        height: 1,
+        depth_or_array_layers: 1,
+    }
+}
+
+// mirrored by the LutUniform struct in effect-shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LutUniform {
+    domain_min: [f32; 4],
+    domain_max: [f32; 4],
+    // 0 when there's no LUT, 1 for 1D and 2 for 3D
+    kind: i32,
+    size: i32,
+    columns: i32,
+    _padding: i32,
+}
+
+// the LUT the adjust pass applies, bound at group 2 of the adjust pipeline
+struct LutBinding {
+    _buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    _texture: Texture,
+}
+
+impl LutBinding {
+    // `None` binds a 1x1 placeholder the shader skips
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, layout: &BindGroupLayout, lut: Option<&Lut>) -> Self {
+        let max = max_texture_dimension();
+        let packed = lut.and_then(|lut| lut.packing(max).ok().map(|packing| (lut, packing)));
+        let (uniform, texels, width, height) = match packed {
+            Some((lut, packing)) => {
+                let [r, g, b] = lut.domain_min;
+                let [r2, g2, b2] = lut.domain_max;
+                let uniform = LutUniform {
+                    domain_min: [r, g, b, 0.0],
+                    domain_max: [r2, g2, b2, 1.0],
+                    kind: if lut.kind == LutKind::OneD { 1 } else { 2 },
+                    size: lut.size as i32,
+                    columns: packing.columns as i32,
+                    _padding: 0,
+                };
+                (uniform, lut.texels(packing), packing.width, packing.height)
+            }
+            None => (LutUniform::zeroed(), vec![0.0; 4], 1, 1),
+        };
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lut"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("lut_texture"),
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(16 * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+            label: Some("lut_bind_group"),
+        });
+
+        LutBinding { _buffer: buffer, bind_group, _texture: texture }
+    }
+}
+
+// intermediate texture an effect pass renders into, with the bind group
+// the following pass samples it through
+struct RenderTarget {
+    _texture: Texture,
+    view: TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl RenderTarget {
+    fn new(device: &wgpu::Device, layout: &BindGroupLayout, sampler: &Sampler, size: wgpu::Extent3d) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("effect_target"),
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("effect_target_bind_group"),
+        });
+
+        RenderTarget { _texture: texture, view, bind_group }
+    }
+}
+
+const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vs_entry: &str,
+    fs_entry: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(fs_entry),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: vs_entry,
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fs_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// Largest width or height a source image texture can have.
+pub fn max_texture_dimension() -> u32 {
+    wgpu::Limits::downlevel_webgl2_defaults().max_texture_dimension_2d
+}
+
 pub struct WebImage {
     pub width: u32,
     pub height: u32,
-    pub data: Vec<u8>
+    pub data: Vec<u8>,
+    pub metadata: ImageMetadata,
 }
 
 
@@ -111,7 +445,10 @@ pub struct WebImage {
 // use image::Rgba;
 struct ImageUniform {
     updated: bool,
-    img: Option<WebImage>,
+    // every image loaded, indexed by the UI's image id. Released images
+    // are `None`, ids aren't reused. Only `active` is uploaded.
+    images: Vec<Option<WebImage>>,
+    active: Option<usize>,
     diffuse_texture: Texture,
     texture_bind_group_layout: BindGroupLayout,
     diffuse_texture_view: TextureView,
@@ -127,7 +464,8 @@ impl ImageUniform {
         ) -> Self {
         ImageUniform { 
             updated: false, 
-            img: None, 
+            images: Vec::new(),
+            active: None,
             diffuse_texture: diffuse_texture,
             texture_bind_group_layout: texture_bind_group_layout,
             diffuse_texture_view,
@@ -144,20 +482,43 @@ impl ImageUniform {
         }
     }
 
+    fn img(&self) -> Option<&WebImage> {
+        self.active.and_then(|id| self.images.get(id)).and_then(|img| img.as_ref())
+    }
+
+    // adds `img` to the session and shows it
     fn update_tex(&mut self, img: WebImage) {
-        self.img = Some(img);
+        self.images.push(Some(img));
+        self.active = Some(self.images.len() - 1);
         self.updated = true;
     }
 
-    fn get_pixels(&self) -> &Vec<u8> {
-        match &self.img {
+    // `None` shows nothing, as before the first image was loaded
+    fn select(&mut self, id: Option<usize>) {
+        let id = id.filter(|&id| matches!(self.images.get(id), Some(Some(_))));
+        if self.active != id {
+            self.active = id;
+            self.updated = true;
+        }
+    }
+
+    fn release(&mut self, id: usize) {
+        if self.active != Some(id) {
+            if let Some(img) = self.images.get_mut(id) {
+                *img = None;
+            }
+        }
+    }
+
+    fn get_pixels(&self) -> &[u8] {
+        match self.img() {
             Some(img) => &img.data,
-            None => panic!("Requested pixels of empty image") 
+            None => &[0; 4],
         }
     }
 
     fn get_dims(&self) -> (u32, u32) {
-        match &self.img {
+        match self.img() {
             Some(img) => (img.width, img.height),
             None => (1 as u32, 1 as u32)
         }
@@ -192,17 +553,44 @@ struct State {
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    pipeline: wgpu::RenderPipeline,
+    adjust_pipeline: wgpu::RenderPipeline,
+    effect_pipeline: wgpu::RenderPipeline,
+    present_pipeline: wgpu::RenderPipeline,
     vertex_buffer: wgpu::Buffer,
     diffuse_bind_group: wgpu::BindGroup,
     image_tex_uniform: ImageUniform,
     input_uniform: InputUniform,
     input_buffer: wgpu::Buffer,
     input_bind_group: wgpu::BindGroup,
+    export_pipeline: wgpu::RenderPipeline,
+    export_input_buffer: wgpu::Buffer,
+    export_input_bind_group: wgpu::BindGroup,
+    effects: EffectStack,
+    layer_bindings: Vec<LayerBinding>,
+    targets: [RenderTarget; 2],
+    // the last target is scaled to the canvas or export size, nearest
+    // minification would alias it
+    target_sampler: Sampler,
+    adjustments: Adjustments,
+    adjustment_buffer: wgpu::Buffer,
+    palette: PaletteUniform,
+    palette_buffer: wgpu::Buffer,
+    // the entry `palette` was made from, for the gradients that follow it
+    palette_colors: ColorEntry,
+    lut_bind_group_layout: BindGroupLayout,
+    lut_binding: LutBinding,
+    curves_binding: CurvesBinding,
+    // gets the output histogram once it's read back
+    on_histogram: Rc<dyn Fn(Histogram)>,
+    // the output changed since the histogram was last read
+    histogram_dirty: bool,
+    // bumped on every change, so a readback can tell it's been overtaken
+    output_generation: Rc<Cell<u64>>,
+    histogram_pending: Rc<Cell<bool>>,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    async fn new(window: &Window, on_histogram: Rc<dyn Fn(Histogram)>) -> Self {
         let size= window.inner_size();
         let instance = wgpu::Instance::new(wgpu::Backends::all());
         let surface = unsafe { instance.create_surface(window) };
@@ -339,6 +727,25 @@ impl State {
             }
         );
 
+        let adjustments = Adjustments::new();
+        let adjustment_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("adjustments"),
+                contents: bytemuck::cast_slice(&[AdjustmentUniform::new(&adjustments)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let palette_colors = ColorList::new().get_color(0).clone();
+        let palette = PaletteUniform::new(&palette_colors);
+        let palette_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("palette"),
+                contents: bytemuck::cast_slice(&[palette]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
         let input_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
@@ -347,7 +754,27 @@ impl State {
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<[i32; 4]>() as u64),
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<InputUniform>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<AdjustmentUniform>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PaletteUniform>() as u64),
                     },
                     count: None,
                 }
@@ -361,13 +788,202 @@ impl State {
                 wgpu::BindGroupEntry {
                     binding: 0,
                     resource: input_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: adjustment_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: palette_buffer.as_entire_binding(),
                 }
             ],
             label: Some("input_bind_group"),
         });
 
-            
+        let export_input_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("export input"),
+                contents: bytemuck::cast_slice(&[InputUniform::stretched(1.0)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let export_input_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &input_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: export_input_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: adjustment_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: palette_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("export_input_bind_group"),
+        });
+
+        let layer_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<LayerUniform>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                }
+            ],
+            label: Some("layer_bind_group_layout"),
+        });
+
+        // ends of a gradient are held rather than wrapped
+        let gradient_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
 
+        let effects = EffectStack::new();
+        let layer_bindings = (0..MAX_LAYERS).map(|_| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("layer params"),
+                size: std::mem::size_of::<LayerUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let gradient_texture = device.create_texture(&wgpu::TextureDescriptor {
+                size: gradient_size(),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                label: Some("gradient_texture"),
+            });
+            let gradient_view = gradient_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layer_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&gradient_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&gradient_sampler),
+                    }
+                ],
+                label: Some("layer_bind_group"),
+            });
+            LayerBinding { buffer, bind_group, gradient_texture, baked: None }
+        }).collect();
+
+
+        let lut_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<LutUniform>() as u64),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    // WebGL2 can't filter float textures, the shader interpolates itself
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("lut_bind_group_layout"),
+        });
+        let lut_binding = LutBinding::new(&device, &queue, &lut_bind_group_layout, None);
+
+        let curves_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                }
+            ],
+            label: Some("curves_bind_group_layout"),
+        });
+        // already display encoded, so not an sRGB format
+        let curves_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: curves_size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("curves_texture"),
+        });
+        let curves_view = curves_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let curves_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &curves_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&curves_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&gradient_sampler),
+                }
+            ],
+            label: Some("curves_bind_group"),
+        });
+        let curves_binding = CurvesBinding { bind_group: curves_bind_group, texture: curves_texture, baked: None };
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
@@ -383,40 +999,40 @@ impl State {
             push_constant_ranges: &[]
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent{ 
-                            src_factor: wgpu::BlendFactor::SrcAlpha, 
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha, 
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let effect_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Effect Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &input_bind_group_layout,
+                &layer_bind_group_layout,
+                ],
+            push_constant_ranges: &[]
+        });
+
+        let adjust_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Adjust Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &input_bind_group_layout,
+                &lut_bind_group_layout,
+                &curves_bind_group_layout,
+                ],
+            push_constant_ranges: &[]
         });
 
+        let adjust_pipeline = create_pipeline(&device, &adjust_pipeline_layout, &shader, "vs_pass", "fs_adjust", TARGET_FORMAT, None);
+        let effect_pipeline = create_pipeline(&device, &effect_pipeline_layout, &shader, "vs_pass", "fs_effect", TARGET_FORMAT, None);
+        let present_blend = wgpu::BlendState {
+            color: wgpu::BlendComponent{ 
+                src_factor: wgpu::BlendFactor::SrcAlpha, 
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha, 
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER
+        };
+        let present_pipeline = create_pipeline(&device, &pipeline_layout, &shader, "vs_main", "fs_main", config.format, Some(present_blend));
+        let export_pipeline = create_pipeline(&device, &pipeline_layout, &shader, "vs_main", "fs_main", TARGET_FORMAT, Some(present_blend));
+
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(VERTICES),
@@ -424,6 +1040,21 @@ impl State {
         });
 
         let mut image_tex_uniform = ImageUniform::new(diffuse_texture, texture_bind_group_layout, diffuse_texture_view, diffuse_sampler);
+        let target_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::MirrorRepeat,
+            address_mode_v: wgpu::AddressMode::MirrorRepeat,
+            address_mode_w: wgpu::AddressMode::MirrorRepeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let targets = [(); 2].map(|_| RenderTarget::new(
+            &device,
+            &image_tex_uniform.texture_bind_group_layout,
+            &target_sampler,
+            image_tex_uniform.get_texture_size(),
+        ));
 
         Self {
             surface,
@@ -431,16 +1062,41 @@ impl State {
             queue,
             config,
             size,
-            pipeline,
+            adjust_pipeline,
+            effect_pipeline,
+            present_pipeline,
             vertex_buffer,
             diffuse_bind_group,
             image_tex_uniform: image_tex_uniform,
             input_uniform,
             input_buffer,
             input_bind_group,
+            export_pipeline,
+            export_input_buffer,
+            export_input_bind_group,
+            effects,
+            layer_bindings,
+            targets,
+            target_sampler,
+            adjustments,
+            adjustment_buffer,
+            palette,
+            palette_buffer,
+            palette_colors,
+            lut_bind_group_layout,
+            lut_binding,
+            curves_binding,
+            on_histogram,
+            histogram_dirty: false,
+            output_generation: Rc::new(Cell::new(0)),
+            histogram_pending: Rc::new(Cell::new(false)),
         }
     }
 
+    fn set_lut(&mut self, lut: Option<Lut>) {
+        self.lut_binding = LutBinding::new(&self.device, &self.queue, &self.lut_bind_group_layout, lut.as_ref());
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
@@ -458,6 +1114,15 @@ impl State {
     fn update(&mut self) {
         self.update_tex_if_needed();
         self.queue.write_buffer(&self.input_buffer, 0, bytemuck::cast_slice(&[self.input_uniform]));
+        for (layer, binding) in self.effects.layers().iter().zip(&mut self.layer_bindings) {
+            self.queue.write_buffer(&binding.buffer, 0, bytemuck::cast_slice(&[LayerUniform::new(layer)]));
+            if let Some(gradient) = layer.gradient(&self.palette_colors) {
+                binding.upload_gradient(&self.queue, gradient);
+            }
+        }
+        self.queue.write_buffer(&self.adjustment_buffer, 0, bytemuck::cast_slice(&[AdjustmentUniform::new(&self.adjustments)]));
+        self.curves_binding.upload(&self.queue, self.adjustments.curves);
+        self.queue.write_buffer(&self.palette_buffer, 0, bytemuck::cast_slice(&[self.palette]));
     }
 
     fn update_tex_if_needed(&mut self) {
@@ -502,18 +1167,118 @@ impl State {
                 // Tells wgpu where to copy the pixel data
                 self.image_tex_uniform.img_copy_tex(),
                 // The actual pixel data
-                &self.image_tex_uniform.get_pixels(),
+                self.image_tex_uniform.get_pixels(),
                 // The layout of the texture
                 self.image_tex_uniform.image_data_layout(),
                 self.image_tex_uniform.get_texture_size(),
             );
             self.image_tex_uniform.updated = false;
+            self.targets = [(); 2].map(|_| RenderTarget::new(
+                &self.device,
+                &self.image_tex_uniform.texture_bind_group_layout,
+                &self.target_sampler,
+                self.image_tex_uniform.get_texture_size(),
+            ));
             let (width, height) = self.image_tex_uniform.get_dims();
             self.input_uniform.img_ratio = (width as f32)/ (height as f32);
             
         }
     }
 
+    // draws one full-quad pass from `input` into `target`. `extra` are the
+    // groups from 2 on, the layer for effect passes and the LUT and curves
+    // for the adjust pass
+    fn encode_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        input: &wgpu::BindGroup,
+        extra: &[&wgpu::BindGroup],
+        target: &TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Effect pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, input, &[]);
+        render_pass.set_bind_group(1, &self.input_bind_group, &[]);
+        for (index, group) in (2..).zip(extra) {
+            render_pass.set_bind_group(index, group, &[]);
+        }
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+
+    // runs the adjustments and every enabled layer, ping-ponging between the
+    // two targets. Returns the index of the target holding the result.
+    fn encode_effect_stack(&self, encoder: &mut wgpu::CommandEncoder) -> usize {
+        self.encode_pass(
+            encoder,
+            &self.adjust_pipeline,
+            &self.diffuse_bind_group,
+            &[&self.lut_binding.bind_group, &self.curves_binding.bind_group],
+            &self.targets[0].view,
+        );
+
+        let mut current = 0;
+        for (layer, binding) in self.effects.layers().iter().zip(&self.layer_bindings) {
+            if !layer.enabled {
+                continue;
+            }
+            let next = 1 - current;
+            self.encode_pass(
+                encoder,
+                &self.effect_pipeline,
+                &self.targets[current].bind_group,
+                &[&binding.bind_group],
+                &self.targets[next].view,
+            );
+            current = next;
+        }
+        current
+    }
+
+    // composites the stack result in `targets[source]` over the background
+    fn encode_present(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        input_bind_group: &wgpu::BindGroup,
+        source: usize,
+        target: &TextureView,
+    ) {
+        // the palette is already linear, as the sRGB target expects
+        let [r, g, b, _] = self.palette.bg.map(|c| c as f64);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a: 1.0 }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.targets[source].bind_group, &[]); 
+        render_pass.set_bind_group(1, input_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..1);
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
         let view = output
@@ -524,35 +1289,9 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder")
             });
-        
-        {
-            let r: f64 = 0.16471;
-            let g: f64 = 0.08627;
-            let b: f64 = 0.67843;
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: r.powf(2.2),
-                            g: g.powf(2.2),
-                            b: b.powf(2.2),
-                            a: 1.0
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
 
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]); 
-            render_pass.set_bind_group(1, &self.input_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..6, 0..1);
-        }
+        let result = self.encode_effect_stack(&mut encoder);
+        self.encode_present(&mut encoder, &self.present_pipeline, &self.input_bind_group, result, &view);
 
         self.queue.submit(iter::once(encoder.finish()));
         output.present();
@@ -560,31 +1299,206 @@ impl State {
         Ok(())
     }
 
+    // Renders the stack offscreen at the export size (the source image's by
+    // default) and hands the pixels to the export encoder.
+    fn export(&mut self, options: ExportOptions) {
+        if self.image_tex_uniform.img().is_none() {
+            log::warn!("no image to export");
+            return;
+        }
+        let source = match &options.overlay {
+            Some(overlay) => overlay.frame_size(self.image_tex_uniform.get_dims()),
+            None => self.image_tex_uniform.get_dims(),
+        };
+        let (width, height) = options.resolve_size(source, max_texture_dimension());
+        let metadata = self.image_tex_uniform.img()
+            .map(|img| img.metadata.clone())
+            .unwrap_or_default();
+
+        let ratio = width as f32 / height as f32;
+        // with the panels drawn in, the image sits in the frame the way it does on the canvas
+        let input = match options.overlay {
+            Some(_) => InputUniform { window_ratio: ratio, ..self.input_uniform },
+            None => InputUniform::stretched(ratio),
+        };
+
+        self.read_back(width, height, input, move |data| match data {
+            Some(data) => {
+                let mut img = WebImage { width, height, data, metadata };
+                if let Some(overlay) = &options.overlay {
+                    if let Err(e) = overlay.composite(&mut img) {
+                        log::error!("couldn't draw the panels into the export: {}", e);
+                    }
+                }
+                export::save(&img, &options);
+            }
+            None => log::error!("couldn't read back the export texture"),
+        });
+    }
+
+    fn invalidate_histogram(&mut self) {
+        self.histogram_dirty = true;
+        self.output_generation.set(self.output_generation.get() + 1);
+    }
+
+    // Counts the output scaled down to OUTPUT_SIZE, which is plenty for
+    // the shape of a histogram and keeps the readback cheap. Called every
+    // frame, it only reads when the output changed and no readback is
+    // still mapping, so a drag doesn't queue one up per input event.
+    fn read_histogram(&mut self) {
+        if !self.histogram_dirty || self.histogram_pending.get() {
+            return;
+        }
+        self.histogram_dirty = false;
+        if self.image_tex_uniform.img().is_none() {
+            return;
+        }
+        let (width, height) = ExportOptions::default().resolve_size(self.image_tex_uniform.get_dims(), histogram::OUTPUT_SIZE);
+        let on_histogram = self.on_histogram.clone();
+        let pending = self.histogram_pending.clone();
+        let latest = self.output_generation.clone();
+        let generation = latest.get();
+        pending.set(true);
+        self.read_back(width, height, InputUniform::stretched(width as f32 / height as f32), move |data| {
+            pending.set(false);
+            // the output changed while this was mapping, the next frame reads it again
+            if latest.get() != generation {
+                return;
+            }
+            if let Some(data) = data {
+                on_histogram(Histogram::from_rgba(&data));
+            }
+        });
+    }
+
+    // Renders the stack over the background into a `width` x `height`
+    // texture and reads it back as RGBA8. Mapping is asynchronous on the
+    // web, so `done` is called from a spawned task, with `None` if mapping failed.
+    fn read_back(&mut self, width: u32, height: u32, input: InputUniform, done: impl FnOnce(Option<Vec<u8>>) + 'static) {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TARGET_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            label: Some("readback_texture"),
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let unpadded_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(align) * align;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (padded_row * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        self.queue.write_buffer(&self.export_input_buffer, 0, bytemuck::cast_slice(&[input]));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder")
+            });
+        let result = self.encode_effect_stack(&mut encoder);
+        self.encode_present(&mut encoder, &self.export_pipeline, &self.export_input_bind_group, result, &view);
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
 
+        spawn_local(async move {
+            match receiver.await {
+                Ok(Ok(())) => {
+                    let data = {
+                        let padded = buffer.slice(..).get_mapped_range();
+                        padded
+                            .chunks(padded_row as usize)
+                            .flat_map(|row| &row[..unpadded_row as usize])
+                            .copied()
+                            .collect()
+                    };
+                    buffer.unmap();
+                    done(Some(data));
+                }
+                _ => done(None),
+            }
+        });
+    }
+
+    fn save_project(&self, mut project: Project, embed: bool) {
+        if embed {
+            for index in 0..project.images.len() {
+                let id = project.images[index].id;
+                if let Some(Some(img)) = self.image_tex_uniform.images.get(id) {
+                    project.embed(index, img);
+                }
+            }
+        }
+        project.save();
+    }
 }
 
 pub enum FrontendEvent {
-    STEP,
     FILL_MODE,
-    NEW_COLORS,
+    /// Colors for the background and the layers that follow the palette.
+    SetPalette(ColorEntry),
     NewImage(WebImage),
+    /// Shows an image already loaded, by id, with the effects, adjustments
+    /// and LUT the UI kept for it. `None` clears the canvas.
+    SelectImage(Option<usize>, EffectStack, Adjustments, Option<Lut>),
+    /// Frees an image that is no longer in the session or the undo history.
+    ReleaseImage(usize),
+    /// Replaces the whole stack, used by undo and redo.
+    SetEffects(EffectStack),
+    SetAdjustments(Adjustments),
+    SetFillMode(bool),
+    /// Downloads `project`, embedding each image's pixels when the flag is set.
+    SaveProject(Project, bool),
+    AddEffect(Effect),
+    RemoveEffect(usize),
+    MoveEffect(usize, usize),
+    ToggleEffect(usize),
+    ToggleEffectPalette(usize),
+    StepEffect(usize),
+    SetEffectParam(usize, EffectParam),
+    SetAdjustment(Adjustment),
+    /// The `.cube` LUT applied after the adjustments, `None` removes it.
+    SetLut(Option<Lut>),
+    Export(ExportOptions),
 }
 
 use wasm_bindgen::JsCast;
-pub fn run(view_width: f64, view_height: f64) -> EventLoopProxy<FrontendEvent>{
+/// Starts the renderer on the canvas. `on_histogram` is called with the
+/// output's histogram whenever an event changes what's drawn.
+pub fn run(view_width: f64, view_height: f64, on_histogram: impl Fn(Histogram) + 'static) -> EventLoopProxy<FrontendEvent>{
 
     
     let event_loop = EventLoopBuilder::<FrontendEvent>::with_user_event().build();
-    let canvas_el = web_sys::window()
-        .and_then(|win| win.document())
-        .and_then(|doc| {
-            doc.get_element_by_id("canvas")
-        })
-        .unwrap();
-    
-    let canvas = canvas_el.dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|_| ())
-        .unwrap();
 
     //let img_ratio = img.width() as f64 / img.height() as f64;
     //let view_ratio = view_width / view_height;
@@ -600,18 +1514,38 @@ pub fn run(view_width: f64, view_height: f64) -> EventLoopProxy<FrontendEvent>{
         render_width = view_width;
     }*/
 
-    use winit::platform::web::WindowBuilderExtWebSys;
+    // winit only has the canvas hookup on wasm. Gating it keeps the crate
+    // building natively, which is what `cargo test` runs on.
+    #[cfg(target_arch = "wasm32")]
+    let window_builder = {
+        use winit::platform::web::WindowBuilderExtWebSys;
 
-    let window = WindowBuilder::new()
-        .with_canvas(Some(canvas))
-        .with_prevent_default(false)
-        .with_focusable(false)
+        let canvas_el = web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| {
+                doc.get_element_by_id("canvas")
+            })
+            .unwrap();
+        
+        let canvas = canvas_el.dyn_into::<web_sys::HtmlCanvasElement>()
+            .map_err(|_| ())
+            .unwrap();
+
+        WindowBuilder::new()
+            .with_canvas(Some(canvas))
+            .with_prevent_default(false)
+            .with_focusable(false)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let window_builder = WindowBuilder::new();
+
+    let window = window_builder
         .build(&event_loop)
         .unwrap();
     use winit::dpi::LogicalSize;
     window.set_inner_size(LogicalSize::new(render_width, render_height));
 
-    let mut state = pollster::block_on(State::new(&window));
+    let mut state = pollster::block_on(State::new(&window, Rc::new(on_histogram)));
     state.update();
     match state.render() {
         Ok(_) => {}
@@ -626,15 +1560,40 @@ pub fn run(view_width: f64, view_height: f64) -> EventLoopProxy<FrontendEvent>{
                 // normally the render code would go here for a game
             }
             Event::MainEventsCleared => {
+                state.read_histogram();
                 window.request_redraw();
             }
             Event::UserEvent(event) => { // custom event from proxy
-
+                // exporting and saving don't change the output
+                let recount = !matches!(event, FrontendEvent::Export(_) | FrontendEvent::SaveProject(..));
                 match event {
-                    FrontendEvent::STEP => state.input_uniform.step(),
                     FrontendEvent::FILL_MODE => state.input_uniform.toggle_fill(),
-                    FrontendEvent::NEW_COLORS => (),
-                    FrontendEvent::NewImage(img) => state.image_tex_uniform.update_tex(img)
+                    FrontendEvent::SetPalette(colors) => {
+                        state.palette = PaletteUniform::new(&colors);
+                        state.palette_colors = colors;
+                    }
+                    FrontendEvent::NewImage(img) => state.image_tex_uniform.update_tex(img),
+                    FrontendEvent::SelectImage(index, effects, adjustments, lut) => {
+                        state.image_tex_uniform.select(index);
+                        state.effects = effects;
+                        state.adjustments = adjustments;
+                        state.set_lut(lut);
+                    }
+                    FrontendEvent::ReleaseImage(id) => state.image_tex_uniform.release(id),
+                    FrontendEvent::SetEffects(effects) => state.effects = effects,
+                    FrontendEvent::SetAdjustments(adjustments) => state.adjustments = adjustments,
+                    FrontendEvent::SetFillMode(fill) => state.input_uniform.set_fill(fill),
+                    FrontendEvent::SaveProject(project, embed) => state.save_project(project, embed),
+                    FrontendEvent::AddEffect(effect) => state.effects.add(effect),
+                    FrontendEvent::RemoveEffect(index) => state.effects.remove(index),
+                    FrontendEvent::MoveEffect(from, to) => state.effects.move_layer(from, to),
+                    FrontendEvent::ToggleEffect(index) => state.effects.toggle(index),
+                    FrontendEvent::ToggleEffectPalette(index) => state.effects.toggle_palette(index),
+                    FrontendEvent::StepEffect(index) => state.effects.step(index),
+                    FrontendEvent::SetEffectParam(index, param) => state.effects.set(index, param),
+                    FrontendEvent::SetAdjustment(adjustment) => state.adjustments.set(adjustment),
+                    FrontendEvent::SetLut(lut) => state.set_lut(lut),
+                    FrontendEvent::Export(options) => state.export(options),
                 }
 
                 state.update();
@@ -644,6 +1603,9 @@ pub fn run(view_width: f64, view_height: f64) -> EventLoopProxy<FrontendEvent>{
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     Err(e) => eprintln!("{:?}", e),
                 }
+                if recount {
+                    state.invalidate_histogram();
+                }
             }
             _ => {}
         });
@@ -0,0 +1,71 @@
+use std::io::Cursor;
+
+use image::{imageops, DynamicImage, ImageOutputFormat, RgbaImage};
+
+use crate::color_management::ColorList;
+use crate::effects::{Adjustments, EffectStack};
+use crate::histogram::Histogram;
+use crate::image_decode::{object_url, revoke_object_url};
+use crate::lut::Lut;
+use crate::metadata::ImageMetadata;
+use crate::render_pipeline::WebImage;
+
+/// Longest side of a filmstrip thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 96;
+
+/// UI side record of one loaded image. The pixels live with the renderer,
+/// which is told which one to show by index. `effects`, `adjustments` and
+/// `lut` are saved when switching away, so they're stale for the active image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionImage {
+    /// Index of the pixels in the renderer, see `FrontendEvent::NewImage`.
+    pub id: usize,
+    pub name: String,
+    /// Object URL of a small PNG, `None` if encoding it failed.
+    pub thumbnail: Option<String>,
+    pub metadata: ImageMetadata,
+    pub effects: EffectStack,
+    pub adjustments: Adjustments,
+    pub lut: Option<Lut>,
+    /// Colors extracted on load, cycled when the palette is taken from the image.
+    pub palette: ColorList,
+    /// Of the source pixels, counted on load.
+    pub histogram: Histogram,
+}
+
+impl SessionImage {
+    pub fn new(id: usize, name: String, img: &WebImage, effects: EffectStack, adjustments: Adjustments, lut: Option<Lut>) -> Self {
+        SessionImage {
+            id,
+            name,
+            thumbnail: thumbnail_url(img),
+            metadata: img.metadata.clone(),
+            effects,
+            adjustments,
+            lut,
+            palette: ColorList::from_image(img),
+            histogram: Histogram::from_rgba(&img.data),
+        }
+    }
+}
+
+impl SessionImage {
+    /// Frees the thumbnail once the image can't come back into the session.
+    pub fn release(&self) {
+        if let Some(url) = &self.thumbnail {
+            revoke_object_url(url);
+        }
+    }
+}
+
+fn thumbnail_url(img: &WebImage) -> Option<String> {
+    let buffer = RgbaImage::from_raw(img.width, img.height, img.data.clone())?;
+    let scale = (THUMBNAIL_SIZE as f32 / img.width.max(img.height) as f32).min(1.0);
+    let width = ((img.width as f32 * scale).round() as u32).max(1);
+    let height = ((img.height as f32 * scale).round() as u32).max(1);
+    let small = imageops::thumbnail(&buffer, width, height);
+
+    let mut bytes = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(small).write_to(&mut bytes, ImageOutputFormat::Png).ok()?;
+    object_url(bytes.get_ref(), "image/png")
+}